@@ -1,23 +1,121 @@
 use std::{
-    fs::File,
-    io::{self, BufReader, BufWriter},
+    collections::{HashMap, HashSet},
+    fs::{File, Metadata, OpenOptions},
+    io::{self, BufReader, Write},
     path::{Path, PathBuf},
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        mpsc::{Receiver, Sender},
-        Arc,
-    },
+    sync::mpsc::{Receiver, Sender},
 };
 
 use ignore::DirEntry;
+use zip::{write::FileOptions, AesMode, DateTime, ZipArchive, ZipWriter};
+
+use super::dedupe::Dedupe;
+use crate::{
+    digest::HashReader,
+    manifest::{mtime_secs, Manifest, ManifestEntry, ManifestFormat},
+};
+
+/// what is already inside an archive opened with `--update`
+#[derive(Debug, Default)]
+struct Existing {
+    /// entry name -> uncompressed size, from the central directory
+    names: HashMap<String, u64>,
+    /// source path -> (size, mtime), from the manifests
+    sources: HashMap<PathBuf, (u64, Option<u64>)>,
+    manifests: usize,
+}
+
+impl Existing {
+    fn read(file: &mut File, password: Option<&str>) -> crate::Result<Self> {
+        let mut archive = ZipArchive::new(file)?;
+        let mut existing = Self::default();
+        for i in 0..archive.len() {
+            let entry = match password {
+                Some(password) => archive.by_index_decrypt(i, password.as_bytes())?,
+                None => archive.by_index(i)?,
+            };
+            let name = entry.name().to_owned();
+            existing.names.insert(name.clone(), entry.size());
+            let Some(format) = ManifestFormat::from_file_name(&name) else {
+                continue;
+            };
+            existing.manifests += 1;
+            match format.read_entries(entry) {
+                Ok(entries) => existing
+                    .sources
+                    .extend(entries.into_iter().map(|x| (x.source, (x.size, x.mtime)))),
+                Err(err) => log::warn!("Failed to read manifest '{name}' - (Reason: {err})"),
+            }
+        }
+        Ok(existing)
+    }
+
+    /// file is unchanged if the manifest know it with same size and mtime,
+    /// without any manifest fallback to the entry name and size
+    fn is_unchanged(&self, source: &Path, fname: &str, metadata: &Metadata) -> bool {
+        if self.manifests > 0 {
+            self.sources.get(source) == Some(&(metadata.len(), mtime_secs(metadata)))
+        } else {
+            self.names.get(fname) == Some(&metadata.len())
+        }
+    }
+}
+
+/// where to take the password for `--encrypt` from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZipPassword {
+    Prompt,
+    Env(String),
+    File(PathBuf),
+}
+
+impl ZipPassword {
+    pub fn read(self) -> crate::Result<String> {
+        let password = match self {
+            Self::Prompt => {
+                let password = rpassword::prompt_password("Zip password: ")?;
+                if password != rpassword::prompt_password("Confirm zip password: ")? {
+                    return Err("password does not match".into());
+                }
+                password
+            }
+            Self::Env(var) => std::env::var(&var)
+                .map_err(|err| format!("password env var `{var}` - (Reason: {err})"))?,
+            Self::File(path) => std::fs::read_to_string(path)?
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+        };
+        if password.is_empty() {
+            return Err("refusing to encrypt with an empty password".into());
+        }
+        Ok(password)
+    }
+}
 
-#[derive(Clone)]
 pub struct AppZip {
-    zipfilepath: Arc<Path>,
-    counter: Arc<AtomicUsize>,
+    zipfilepath: PathBuf,
+    /// taken out when the archive is finished
+    writer: Option<ZipWriter<File>>,
+    password: Option<String>,
+    manifest: Manifest,
+    existing: Existing,
+    names: HashSet<String>,
+    manifest_name: String,
+    counter: usize,
+    deterministic: bool,
+    dedupe: Option<Dedupe>,
 }
 impl AppZip {
-    pub fn new(zipfilepath: PathBuf) -> crate::Result<Self> {
+    pub fn new(
+        zipfilepath: PathBuf,
+        manifest: Manifest,
+        update: bool,
+        password: Option<String>,
+        deterministic: bool,
+        dedupe: bool,
+    ) -> crate::Result<Self> {
         let Some(dest) = zipfilepath.parent() else {
             return Err(crate::error::ColekError::Err(format!(
                 "failed to get parrent path: '{}' - path terminates in root",
@@ -25,72 +123,210 @@ impl AppZip {
             )));
         };
         std::fs::create_dir_all(dest)?;
+        let (writer, existing) = if update && zipfilepath.exists() {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&zipfilepath)?;
+            let existing = Existing::read(&mut file, password.as_deref())?;
+            log::info!(
+                "Updating Zip Archive '{}' with {} entries",
+                zipfilepath.display(),
+                existing.names.len()
+            );
+            (ZipWriter::new_append(file)?, existing)
+        } else {
+            let file = File::create(&zipfilepath)?;
+            (ZipWriter::new(file), Existing::default())
+        };
+        let mut names: HashSet<String> = existing.names.keys().cloned().collect();
+        // reserved before any scanned file, a file named like the manifest can not take its name
+        let manifest_name = (existing.manifests..)
+            .map(|n| manifest.format().file_name(n))
+            .find(|name| !names.contains(name))
+            .expect("should never fail");
+        names.insert(manifest_name.clone());
         Ok(Self {
-            zipfilepath: zipfilepath.into(),
-            counter: Arc::new(AtomicUsize::new(0)),
+            zipfilepath,
+            writer: Some(writer),
+            password,
+            manifest,
+            names,
+            manifest_name,
+            existing,
+            counter: 0,
+            deterministic,
+            dedupe: dedupe.then(Dedupe::default),
         })
     }
+
+    /// entries are encrypted with WinZip AES-256 when there is a password, never with ZipCrypto
+    /// and with `--deterministic` the timestamp is normalised instead of depending on the clock
+    fn options(password: Option<&str>, deterministic: bool) -> FileOptions<'_, ()> {
+        let mut options = FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .unix_permissions(0o755);
+        if deterministic {
+            options = options.last_modified_time(DateTime::default());
+        }
+        match password {
+            Some(password) => options.with_aes_encryption(AesMode::Aes256, password),
+            None => options,
+        }
+    }
+
+    /// reopen the finished archive and check every new entry is AES-256 encrypted,
+    /// and the manifest can be decrypted back with the password
+    fn verify_encryption(&self, password: &str, manifest: &str) -> crate::Result<()> {
+        let mut archive = ZipArchive::new(BufReader::new(File::open(&self.zipfilepath)?))?;
+        for i in 0..archive.len() {
+            let name = archive.name_for_index(i).unwrap_or_default().to_owned();
+            if self.existing.names.contains_key(&name) {
+                continue;
+            }
+            match archive.get_aes_verification_key_and_salt(i)? {
+                Some(info) if info.aes_mode == AesMode::Aes256 => {}
+                _ => return Err(format!("entry '{name}' is not encrypted with AES-256").into()),
+            }
+        }
+        let mut file = archive.by_name_decrypt(manifest, password.as_bytes())?;
+        io::copy(&mut file, &mut io::sink())?;
+        log::info!(
+            "Verified AES-256 encryption of '{}'",
+            self.zipfilepath.display()
+        );
+        Ok(())
+    }
+
+    /// `fname` itself when still free, otherwise `<stem>_<n>.<ext>`
+    fn unique_name(&self, fname: &str) -> String {
+        super::numbered_names(fname)
+            .find(|name| !self.names.contains(name))
+            .expect("should never fail")
+    }
+
+    /// reserve an unique entry name for `source`, `None` when it is unchanged in the archive
+    fn reserve_name(&mut self, source: &Path, metadata: &Metadata) -> Option<String> {
+        let fname = source
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.counter.to_string());
+        self.counter += 1;
+        if self.existing.is_unchanged(source, &fname, metadata) {
+            log::debug!("Skip unchanged file: '{}'", source.display());
+            return None;
+        }
+        let fname = self.unique_name(&fname);
+        self.names.insert(fname.clone());
+        Some(fname)
+    }
+
+    fn write_file(
+        &mut self,
+        source: &Path,
+        metadata: &Metadata,
+        fname: String,
+    ) -> crate::Result<()> {
+        let algorithm = self.manifest.algorithm();
+        if let Some(ref dedupe) = self.dedupe {
+            if let Some((stored, hash)) = dedupe.find(source, metadata.len(), algorithm)? {
+                log::info!("Not zipping '{}' - same as {stored}", source.display());
+                self.names.remove(&fname);
+                let mut entry = ManifestEntry::new(source, metadata, hash.to_string(), stored);
+                entry.deduplicated = true;
+                self.manifest.push(entry);
+                return Ok(());
+            }
+        }
+        let mut reader = HashReader::new(BufReader::new(File::open(source)?), algorithm);
+        let options = Self::options(self.password.as_deref(), self.deterministic);
+        let writer = self.writer.as_mut().ok_or("zip archive already finished")?;
+        writer.start_file(&fname, options)?;
+        let copied = io::copy(&mut reader, writer)?;
+        log::info!("Copied file into Zip Archive: {copied} bytes");
+
+        let hash = reader.digest();
+        if let Some(ref mut dedupe) = self.dedupe {
+            dedupe.insert(metadata.len(), hash, fname.clone());
+        }
+        let entry = ManifestEntry::new(source, metadata, hash.to_string(), fname);
+        self.manifest.push(entry);
+        Ok(())
+    }
+
+    fn write_file_logged(&mut self, source: &Path, metadata: &Metadata, fname: String) {
+        if let Err(err) = self.write_file(source, metadata, fname) {
+            log::error!(
+                "Failed to copy from '{}' - (Reason: {err})",
+                source.display()
+            );
+        }
+    }
 }
 
 impl super::App for AppZip {
-    type Item = u64;
+    type Item = (PathBuf, Metadata);
 
     fn name() -> &'static str {
         "Zip"
     }
 
     fn on_blocking(&mut self, rx: Receiver<Self::Item>) -> crate::Result<()> {
-        while let Ok(copied) = rx.recv() {
-            log::info!("Copied file into Zip Archive: {copied} bytes")
+        if !self.deterministic {
+            while let Ok((source, metadata)) = rx.recv() {
+                if let Some(fname) = self.reserve_name(&source, &metadata) {
+                    self.write_file_logged(&source, &metadata, fname);
+                }
+            }
+            return Ok(());
+        }
+        let mut entries: Vec<_> = rx.into_iter().collect();
+
+        // walker order is not stable, names are reserved in source order and written in name order
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut named: Vec<_> = entries
+            .into_iter()
+            .filter_map(|(source, metadata)| {
+                let fname = self.reserve_name(&source, &metadata)?;
+                Some((fname, source, metadata))
+            })
+            .collect();
+        named.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+        for (fname, source, metadata) in named {
+            self.write_file_logged(&source, &metadata, fname);
         }
         Ok(())
     }
 
     fn file_scan(&mut self, tx: Sender<Self::Item>, rx: Receiver<DirEntry>) -> crate::Result<()> {
-        let counter = self.counter.clone();
-        let mut writer = zip::ZipWriter::new(BufWriter::new(File::create(&self.zipfilepath)?));
-        let options = zip::write::FileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated)
-            .unix_permissions(0o755);
-
         rayon::spawn(move || {
             while let Ok(file) = rx.recv() {
-                let path = file.path();
-                let c = counter.fetch_add(1, Ordering::Relaxed);
-                let fname = path
-                    .file_name()
-                    .map(|x| x.to_string_lossy().to_string())
-                    .unwrap_or_else(|| c.to_string());
-                let dest = PathBuf::from(fname);
-                let source = path;
-
-                let Ok(_) = writer.start_file(dest.to_string_lossy(), options) else {
-                    continue;
-                };
-                if let Ok(file) = File::open(source) {
-                    let mut file = BufReader::new(file);
-                    match io::copy(&mut file, &mut writer) {
-                        Ok(ok) => {
-                            tx.send(ok).ok();
-                        }
-                        Err(err) => {
-                            log::error!(
-                                "Failed to copy from '{}' - (Reason: {err})",
-                                source.display()
-                            );
-                        }
+                match file.metadata() {
+                    Ok(metadata) => {
+                        tx.send((file.into_path(), metadata)).ok();
                     }
+                    Err(err) => log::error!(
+                        "Failed to get metadata of '{}' - (Reason: {err})",
+                        file.path().display()
+                    ),
                 }
             }
-
             drop(tx);
-            writer.finish().ok();
         });
 
         Ok(())
     }
 
     fn on_finish(&mut self) -> crate::Result<()> {
-        Ok(())
+        let name = self.manifest_name.clone();
+        let options = Self::options(self.password.as_deref(), self.deterministic);
+        let mut writer = self.writer.take().ok_or("zip archive already finished")?;
+        writer.start_file(&name, options)?;
+        self.manifest.write_to(&mut writer)?;
+        writer.finish()?.flush()?;
+        match self.password {
+            Some(ref password) => self.verify_encryption(password, &name),
+            None => Ok(()),
+        }
     }
 }
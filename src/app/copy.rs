@@ -1,4 +1,7 @@
 use std::{
+    collections::HashSet,
+    fs::File,
+    io::{self, BufReader, BufWriter},
     path::Path,
     sync::{
         mpsc::{Receiver, Sender},
@@ -8,18 +11,92 @@ use std::{
 
 use ignore::DirEntry;
 
-#[derive(Clone, Debug)]
+use super::dedupe::Dedupe;
+use crate::{
+    digest::{hash_file, Digest, HashAlgorithm, HashReader},
+    known::KnownHashes,
+    manifest::{Manifest, ManifestEntry},
+};
+
+#[derive(Debug)]
 pub struct AppCopy {
     dest: Arc<Path>,
+    manifest: Manifest,
+    /// files already in this reference are not copied
+    against: Option<Arc<KnownHashes>>,
+    dedupe: bool,
+    /// name of the manifest, never given to a copied file
+    manifest_name: String,
 }
 impl AppCopy {
-    pub fn new(dest: impl Into<Arc<Path>>) -> crate::Result<Self> {
-        Ok(Self { dest: dest.into() })
+    pub fn new(
+        dest: impl Into<Arc<Path>>,
+        manifest: Manifest,
+        against: Option<KnownHashes>,
+        dedupe: bool,
+    ) -> crate::Result<Self> {
+        let dest: Arc<Path> = dest.into();
+        // a manifest of an earlier copy into the same directory is kept
+        let manifest_name = (0..)
+            .map(|n| manifest.format().file_name(n))
+            .find(|name| !dest.join(name).exists())
+            .expect("should never fail");
+        Ok(Self {
+            dest,
+            manifest,
+            against: against.map(Arc::new),
+            dedupe,
+            manifest_name,
+        })
+    }
+
+    /// whether `source` of `size` bytes is already in the `against` reference,
+    /// hashed with the algorithm of the reference
+    fn is_known(against: &KnownHashes, source: &Path, size: u64) -> bool {
+        if !against.may_contain_size(size) {
+            return false;
+        }
+        match hash_file(source, against.algorithm()) {
+            Ok((hash, _)) => against.contains(&hash),
+            Err(err) => {
+                log::error!("Failed to hash '{}' - {err}", source.display());
+                false
+            }
+        }
+    }
+
+    /// copy `source` into `dest` under `fname` or the first free numbered name after it,
+    /// an existing file is never overwritten, returns the name taken and the hash
+    fn copy_file(
+        source: &Path,
+        dest: &Path,
+        fname: &str,
+        taken: &mut HashSet<String>,
+        algorithm: HashAlgorithm,
+    ) -> io::Result<(String, Digest)> {
+        let mut reader = HashReader::new(BufReader::new(File::open(source)?), algorithm);
+        for name in super::numbered_names(fname) {
+            if !taken.insert(name.clone()) {
+                continue;
+            }
+            let path = dest.join(&name);
+            let file = match File::create_new(&path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            };
+            if let Err(err) = io::copy(&mut reader, &mut BufWriter::new(file)) {
+                std::fs::remove_file(&path).ok();
+                return Err(err);
+            }
+            return Ok((name, reader.digest()));
+        }
+        unreachable!("numbered names never run out")
     }
 }
 
 impl super::App for AppCopy {
-    type Item = usize;
+    type Item = ManifestEntry;
 
     #[inline]
     fn name() -> &'static str {
@@ -28,8 +105,9 @@ impl super::App for AppCopy {
 
     fn on_blocking(&mut self, rx: Receiver<Self::Item>) -> crate::Result<()> {
         let mut counter = 0;
-        while let Ok(c) = rx.recv() {
-            counter = c;
+        while let Ok(entry) = rx.recv() {
+            self.manifest.push(entry);
+            counter += 1;
         }
 
         log::info!("Coping {counter} file(s)");
@@ -38,25 +116,65 @@ impl super::App for AppCopy {
 
     fn file_scan(&mut self, tx: Sender<Self::Item>, rx: Receiver<DirEntry>) -> crate::Result<()> {
         let dest = self.dest.clone();
+        let algorithm = self.manifest.algorithm();
+        let against = self.against.clone();
+        let mut dedupe = self.dedupe.then(Dedupe::default);
+        let mut taken = HashSet::from([self.manifest_name.clone()]);
         rayon::spawn(move || {
             let mut counter = 0;
+            let mut skipped = 0usize;
             while let Ok(file) = rx.recv() {
                 let path = file.path();
+                let size = file.metadata().map_or(0, |x| x.len());
+                if let Some(ref against) = against {
+                    if Self::is_known(against, path, size) {
+                        log::info!("Skipping {} - already in the reference", path.display());
+                        skipped += 1;
+                        continue;
+                    }
+                }
                 let fname = path
                     .file_name()
                     .map(|x| x.to_string_lossy().to_string())
                     .unwrap_or_else(|| counter.to_string());
-                let dest = dest.join(fname);
-
-                match std::fs::copy(path, &dest) {
-                    Ok(k) => {
+                if let Some(ref dedupe) = dedupe {
+                    match dedupe.find(path, size, algorithm) {
+                        Ok(Some((stored, hash))) => {
+                            let Ok(metadata) = file.metadata() else {
+                                continue;
+                            };
+                            log::info!("Not copying {} - same as {stored}", path.display());
+                            let mut entry =
+                                ManifestEntry::new(path, &metadata, hash.to_string(), stored);
+                            entry.deduplicated = true;
+                            tx.send(entry).ok();
+                            continue;
+                        }
+                        Ok(None) => {}
+                        Err(err) => {
+                            log::error!("Failed to hash '{}' - {err}", path.display());
+                            continue;
+                        }
+                    }
+                }
+                match Self::copy_file(path, &dest, &fname, &mut taken, algorithm) {
+                    Ok((fname, hash)) => {
+                        let Ok(metadata) = file.metadata() else {
+                            continue;
+                        };
+                        // only now the name is known to hold this content
+                        if let Some(ref mut dedupe) = dedupe {
+                            dedupe.insert(size, hash, fname.clone());
+                        }
                         log::info!(
                             "Success copying file from {path} into {dest} with size: {k} bytes",
                             path = path.display(),
-                            dest = dest.display()
+                            dest = dest.join(&fname).display(),
+                            k = metadata.len(),
                         );
                         counter += 1;
-                        tx.send(counter).ok();
+                        tx.send(ManifestEntry::new(path, &metadata, hash.to_string(), fname))
+                            .ok();
                     }
                     Err(err) => log::error!(
                         "Failed to copy file `{path}` into `{dest}` - {err}",
@@ -65,6 +183,9 @@ impl super::App for AppCopy {
                     ),
                 }
             }
+            if against.is_some() {
+                log::info!("Skipped {skipped} file(s) already in the reference");
+            }
             drop(tx)
         });
 
@@ -72,6 +193,10 @@ impl super::App for AppCopy {
     }
 
     fn on_finish(&mut self) -> crate::Result<()> {
+        let path = self.dest.join(&self.manifest_name);
+        self.manifest
+            .write_to(BufWriter::new(File::create_new(&path)?))?;
+        log::info!("Manifest written into {}", path.display());
         Ok(())
     }
 }
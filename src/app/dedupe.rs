@@ -0,0 +1,41 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::Path,
+};
+
+use crate::digest::{hash_file, Digest, HashAlgorithm};
+
+/// contents already stored during a collection with `--dedupe`
+#[derive(Debug, Default)]
+pub struct Dedupe {
+    /// a file with a size not stored yet can not be a duplicate, and is not hashed twice
+    sizes: HashSet<u64>,
+    /// (size, hash) -> destination of the stored file
+    stored: HashMap<(u64, Digest), String>,
+}
+
+impl Dedupe {
+    /// destination already holding the contents of `source` with its hash,
+    /// `source` is only hashed when a stored file has the same size
+    pub fn find(
+        &self,
+        source: &Path,
+        size: u64,
+        algorithm: HashAlgorithm,
+    ) -> io::Result<Option<(String, Digest)>> {
+        if !self.sizes.contains(&size) {
+            return Ok(None);
+        }
+        let (hash, _) = hash_file(source, algorithm)?;
+        Ok(self
+            .stored
+            .get(&(size, hash))
+            .map(|dest| (dest.clone(), hash)))
+    }
+
+    pub fn insert(&mut self, size: u64, hash: Digest, dest: String) {
+        self.sizes.insert(size);
+        self.stored.entry((size, hash)).or_insert(dest);
+    }
+}
@@ -9,36 +9,204 @@ use std::{
 };
 
 use ignore::DirEntry;
+use serde_json::Value;
+
+use crate::{
+    filters::{detect_format, Filter},
+    manifest::mtime_secs,
+    system::DiskPartition,
+};
+
+use super::stats::{ScanStats, StatsFormat};
+
+/// how the scanned files are written to stdout
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StdoutFormat {
+    /// one file per line, the fields separated by tabs
+    #[default]
+    Plain,
+    /// like plain but each file ends with a NUL byte, for `xargs -0`
+    Null,
+    /// one json object per file
+    Jsonl,
+    /// csv with a header row
+    Csv,
+}
+
+/// field of a scanned file written to stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StdoutField {
+    Path,
+    /// size in bytes
+    Size,
+    /// modification time, in seconds since the unix epoch
+    Mtime,
+    /// image, video or music
+    Category,
+    /// format detected by extension or magic bytes
+    Format,
+    /// name of the drive the file lives on
+    Drive,
+}
+
+impl StdoutField {
+    const ALL: &'static [Self] = &[
+        Self::Path,
+        Self::Size,
+        Self::Mtime,
+        Self::Category,
+        Self::Format,
+        Self::Drive,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Path => "path",
+            Self::Size => "size",
+            Self::Mtime => "mtime",
+            Self::Category => "category",
+            Self::Format => "format",
+            Self::Drive => "drive",
+        }
+    }
+}
+
+/// a scanned file, as sent to the stdout writer
+#[derive(Debug, Clone, PartialEq)]
+pub struct StdoutRecord {
+    path: PathBuf,
+    size: u64,
+    mtime: Option<u64>,
+    format: Option<String>,
+}
 
 #[derive(Debug)]
 pub struct AppDefault {
     stdout: BufWriter<Stdout>,
+    format: StdoutFormat,
+    fields: Vec<StdoutField>,
+    summary: bool,
+    /// statistics report, written to the file or to stderr
+    stats: Option<(StatsFormat, Option<PathBuf>, ScanStats)>,
+    drives: Vec<DiskPartition>,
+    csv: Option<csv::Writer<Stdout>>,
     count: Arc<AtomicUsize>,
     size: Arc<AtomicUsize>,
 }
 
 impl AppDefault {
-    pub fn new() -> crate::Result<Self> {
+    /// without `fields`, plain and null only write the path while jsonl and csv write every field
+    pub fn new(
+        format: StdoutFormat,
+        fields: Option<Vec<StdoutField>>,
+        summary: bool,
+        stats: Option<(StatsFormat, Option<PathBuf>, usize)>,
+        drives: Vec<DiskPartition>,
+    ) -> crate::Result<Self> {
+        let fields = fields.unwrap_or_else(|| match format {
+            StdoutFormat::Plain | StdoutFormat::Null => vec![StdoutField::Path],
+            StdoutFormat::Jsonl | StdoutFormat::Csv => StdoutField::ALL.to_vec(),
+        });
         Ok(Self {
             stdout: BufWriter::with_capacity(50 << 10, stdout()),
+            format,
+            fields,
+            summary,
+            stats: stats.map(|(format, output, top)| (format, output, ScanStats::new(top))),
+            drives,
+            csv: None,
             count: Arc::new(AtomicUsize::new(0)),
             size: Arc::new(AtomicUsize::new(0)),
         })
     }
+
+    fn value(&self, record: &StdoutRecord, field: StdoutField) -> Value {
+        let category = || {
+            let format = record.format.as_deref()?;
+            Filter::from_extension(format).map(|x| x.to_string().to_lowercase())
+        };
+        match field {
+            StdoutField::Path => record.path.to_string_lossy().into(),
+            StdoutField::Size => record.size.into(),
+            StdoutField::Mtime => record.mtime.into(),
+            StdoutField::Category => category().into(),
+            StdoutField::Format => record.format.clone().into(),
+            StdoutField::Drive => DiskPartition::find(&self.drives, &record.path)
+                .map(|x| x.name.clone())
+                .into(),
+        }
+    }
+
+    fn write_record(&mut self, record: &StdoutRecord) -> crate::Result<()> {
+        let values: Vec<_> = self.fields.iter().map(|x| self.value(record, *x)).collect();
+        let text = |x: &Value| match x {
+            Value::Null => String::new(),
+            Value::String(x) => x.clone(),
+            x => x.to_string(),
+        };
+        match self.format {
+            StdoutFormat::Plain | StdoutFormat::Null => {
+                let line = values.iter().map(text).collect::<Vec<_>>().join("\t");
+                let end = if self.format == StdoutFormat::Null {
+                    '\0'
+                } else {
+                    '\n'
+                };
+                write!(self.stdout, "{line}{end}")?;
+            }
+            StdoutFormat::Jsonl => {
+                // written by hand, so the keys keep the order of `--fields`
+                let members: Vec<_> = self
+                    .fields
+                    .iter()
+                    .zip(&values)
+                    .map(|(field, value)| format!("\"{}\":{value}", field.name()))
+                    .collect();
+                writeln!(self.stdout, "{{{}}}", members.join(","))?;
+            }
+            StdoutFormat::Csv => {
+                // the csv writer keeps its own buffer, the header is written with the first row
+                let csv = match self.csv {
+                    Some(ref mut csv) => csv,
+                    None => {
+                        let mut csv = csv::Writer::from_writer(stdout());
+                        csv.write_record(self.fields.iter().map(|x| x.name()))?;
+                        self.csv.insert(csv)
+                    }
+                };
+                csv.write_record(values.iter().map(text))?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl super::App for AppDefault {
-    type Item = PathBuf;
+    type Item = StdoutRecord;
     fn file_scan(&mut self, tx: Sender<Self::Item>, rx: Receiver<DirEntry>) -> crate::Result<()> {
         log::debug!("{}: on FileScan", Self::name());
         let size = self.size.clone();
         let count = self.count.clone();
+        // detecting the format may read the file, only done when it is written or counted
+        let detect = self.stats.is_some()
+            || self
+                .fields
+                .iter()
+                .any(|x| matches!(x, StdoutField::Category | StdoutField::Format));
         rayon::spawn(move || {
             while let Ok(direntry) = rx.recv() {
-                let s = direntry.metadata().map(|x| x.len()).unwrap_or(0) as usize;
-                size.fetch_add(s, Ordering::Relaxed);
+                let metadata = direntry.metadata().ok();
+                let s = metadata.as_ref().map_or(0, |x| x.len());
+                size.fetch_add(s as usize, Ordering::Relaxed);
                 count.fetch_add(1, Ordering::Relaxed);
-                tx.send(direntry.path().to_path_buf()).ok();
+                let path = direntry.into_path();
+                let record = StdoutRecord {
+                    format: detect.then(|| detect_format(&path)).flatten(),
+                    mtime: metadata.as_ref().and_then(mtime_secs),
+                    size: s,
+                    path,
+                };
+                tx.send(record).ok();
             }
         });
         Ok(())
@@ -46,25 +214,53 @@ impl super::App for AppDefault {
 
     fn on_blocking(&mut self, rx: Receiver<Self::Item>) -> crate::Result<()> {
         log::debug!("{}: on Blocking", Self::name());
-        while let Ok(direntry) = rx.recv() {
-            writeln!(self.stdout, "path: '{}'", direntry.display()).ok();
+        while let Ok(record) = rx.recv() {
+            if let Some((_, _, ref mut stats)) = self.stats {
+                stats.add(
+                    &self.drives,
+                    &record.path,
+                    record.size,
+                    record.mtime,
+                    record.format.as_deref(),
+                );
+            }
+            if let Err(err) = self.write_record(&record) {
+                log::error!("Failed to write '{}' - {err}", record.path.display());
+            }
         }
         Ok(())
     }
 
     fn on_finish(&mut self) -> crate::Result<()> {
         self.stdout.flush().ok();
-        println!("============= Finish Scanning =============");
-        println!(
-            "size scanned            : {}",
+        if let Some(ref mut csv) = self.csv {
+            csv.flush().ok();
+        }
+        if let Some((format, ref output, ref mut stats)) = self.stats {
+            stats.finish();
+            match output {
+                Some(path) => {
+                    stats.write(format, BufWriter::new(std::fs::File::create(path)?))?;
+                    log::info!("Statistics written into {}", path.display());
+                }
+                None => stats.write(format, std::io::stderr())?,
+            }
+        }
+        if !self.summary {
+            return Ok(());
+        }
+        // the summary goes to stderr, stdout only holds the scanned files
+        eprintln!("============= Finish Scanning =============");
+        eprintln!(
+            "files scanned           : {}",
             self.count.load(Ordering::Relaxed)
         );
         let file_size_kb = self.size.load(Ordering::Relaxed) as f32 / 1024.0;
         let file_size_mb = file_size_kb / 1024.0;
         let file_size_gb = file_size_mb / 1024.0;
-        println!("all size bytes(KBytes)  : {:.2} KB", file_size_kb);
-        println!("all size bytes(MBytes)  : {:.2} MB", file_size_mb);
-        println!("all size bytes(GBytes)  : {:.2} GB", file_size_gb);
+        eprintln!("all size bytes(KBytes)  : {:.2} KB", file_size_kb);
+        eprintln!("all size bytes(MBytes)  : {:.2} MB", file_size_mb);
+        eprintln!("all size bytes(GBytes)  : {:.2} GB", file_size_gb);
         Ok(())
     }
 
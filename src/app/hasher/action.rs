@@ -0,0 +1,275 @@
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use super::quarantine::Quarantine;
+use crate::digest::Digest;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HasherEventDuplicate {
+    Remove,
+    Rename,
+    Print,
+    /// replace the duplicate with a hard link to the kept file (same filesystem only)
+    Hardlink,
+    /// replace the duplicate with a symbolic link to the kept file
+    Symlink,
+    /// replace the duplicate with a copy-on-write clone of the kept file
+    Reflink,
+    /// move the duplicate into the `--quarantine` directory, see `colek restore`
+    Quarantine,
+}
+
+/// what the events need beside the duplicate itself
+#[derive(Debug, Clone, Default)]
+pub struct ActionContext {
+    pub quarantine: Option<Quarantine>,
+    /// send removed files to the trash instead of deleting them
+    pub trash: bool,
+    pub rename: RenameTemplate,
+}
+
+/// new file name of renamed duplicates, with the `{stem}`, `{ext}`, `{hash}`
+/// and `{n}` (or `{num}`) placeholders, `.{ext}` is dropped for files without extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenameTemplate(String);
+
+impl Default for RenameTemplate {
+    fn default() -> Self {
+        Self("{stem}-{hash}.{ext}".to_owned())
+    }
+}
+
+impl FromStr for RenameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(['/', '\\']) {
+            return Err(format!(
+                "rename template `{s}` must not contain a path separator"
+            ));
+        }
+        if !s.contains("{stem}") {
+            return Err(format!("rename template `{s}` must contain `{{stem}}`"));
+        }
+        Ok(Self(s.to_owned()))
+    }
+}
+
+impl RenameTemplate {
+    fn render(&self, stem: &str, ext: Option<&str>, hash: &Digest, n: usize) -> String {
+        let mut name = self.0.replace("{num}", "{n}");
+        if ext.is_none() {
+            name = name.replace(".{ext}", "");
+        }
+        let mut stem = stem.to_owned();
+        // without `{n}` in the template the counter goes on the stem, after the first try
+        if !name.contains("{n}") && n > 1 {
+            stem = format!("{stem}_{n}");
+        }
+        name = name
+            .replace("{n}", &n.to_string())
+            .replace("{hash}", &hash.to_string())
+            .replace("{ext}", ext.unwrap_or(""));
+        name.replace("{stem}", &stem)
+    }
+
+    /// first rendered sibling of `path` that does not exist yet
+    pub fn target(&self, path: &Path, hash: &Digest) -> io::Result<PathBuf> {
+        let stem = path.file_stem().map(|x| x.to_string_lossy());
+        let ext = path.extension().map(|x| x.to_string_lossy());
+        let Some(stem) = stem else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path has no file name",
+            ));
+        };
+        (1..)
+            .map(|n| path.with_file_name(self.render(&stem, ext.as_deref(), hash, n)))
+            .find(|target| target.symlink_metadata().is_err())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::AlreadyExists, "no free file name"))
+    }
+}
+
+impl HasherEventDuplicate {
+    /// events that lose the duplicate contents, confirmed byte by byte before applied
+    pub fn is_destructive(self) -> bool {
+        matches!(
+            self,
+            Self::Remove | Self::Rename | Self::Hardlink | Self::Symlink | Self::Reflink
+        )
+    }
+
+    /// apply the event on duplicate `path`, where `keep` is the file that survive
+    pub fn apply(
+        self,
+        ctx: &ActionContext,
+        path: &Path,
+        keep: &Path,
+        hash: &Digest,
+    ) -> io::Result<()> {
+        match self {
+            Self::Remove if ctx.trash => crate::trash::put(path),
+            Self::Remove => fs::remove_file(path),
+            Self::Rename => fs::rename(path, ctx.rename.target(path, hash)?),
+            Self::Print => Ok(()),
+            Self::Hardlink => {
+                if !same_filesystem(path, keep)? {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "kept file is on another filesystem",
+                    ));
+                }
+                replace_with(path, |tmp| fs::hard_link(keep, tmp))
+            }
+            Self::Symlink => {
+                let keep = keep.canonicalize()?;
+                replace_with(path, |tmp| symlink(&keep, tmp))
+            }
+            Self::Reflink => replace_with(path, |tmp| reflink(keep, tmp)),
+            Self::Quarantine => match ctx.quarantine {
+                Some(ref quarantine) => quarantine.put(path, hash),
+                None => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no quarantine directory",
+                )),
+            },
+        }
+    }
+}
+
+/// create the replacement next to `path` then rename it over `path`,
+/// so the duplicate is never lost when creating the replacement fail
+fn replace_with(path: &Path, create: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let mut tmp = OsString::from(path.as_os_str());
+    tmp.push(".colek-tmp");
+    let tmp = PathBuf::from(tmp);
+    create(&tmp)?;
+    fs::rename(&tmp, path).inspect_err(|_| {
+        fs::remove_file(&tmp).ok();
+    })
+}
+
+/// what hard links of one file have in common, `(device, inode)` on unix
+#[cfg(unix)]
+pub type FileId = (u64, u64);
+#[cfg(not(unix))]
+pub type FileId = PathBuf;
+
+#[cfg(unix)]
+pub fn file_id(path: &Path) -> io::Result<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = path.metadata()?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_id(path: &Path) -> io::Result<FileId> {
+    path.canonicalize()
+}
+
+#[cfg(unix)]
+fn same_filesystem(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    Ok(a.metadata()?.dev() == b.metadata()?.dev())
+}
+
+#[cfg(not(unix))]
+fn same_filesystem(a: &Path, b: &Path) -> io::Result<bool> {
+    let root = |p: &Path| p.components().next().map(|x| x.as_os_str().to_owned());
+    Ok(root(&a.canonicalize()?) == root(&b.canonicalize()?))
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reflink(original: &Path, dest: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let src = fs::File::open(original)?;
+    let dst = fs::File::create_new(dest)?;
+    // SAFETY: both file descriptors are valid and owned for the duration of the call
+    let r = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+    if r != 0 {
+        let err = io::Error::last_os_error();
+        drop(dst);
+        fs::remove_file(dest).ok();
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn reflink(_original: &Path, _dest: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflink is only supported on linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest() -> Digest {
+        Digest::from_hex("00ff").expect("valid hex")
+    }
+
+    #[test]
+    fn template_rejects_separators_and_missing_stem() {
+        assert!("{stem}/{hash}".parse::<RenameTemplate>().is_err());
+        assert!("{stem}\\{hash}".parse::<RenameTemplate>().is_err());
+        assert!("{hash}.{ext}".parse::<RenameTemplate>().is_err());
+        assert!("{stem}-{n}.{ext}".parse::<RenameTemplate>().is_ok());
+    }
+
+    #[test]
+    fn render_placeholders() {
+        let template: RenameTemplate = "{stem}-{n}-{hash}.{ext}".parse().unwrap();
+        assert_eq!(
+            template.render("a", Some("jpg"), &digest(), 3),
+            "a-3-00ff.jpg"
+        );
+        let template: RenameTemplate = "{stem}-{num}.{ext}".parse().unwrap();
+        assert_eq!(template.render("a", Some("jpg"), &digest(), 2), "a-2.jpg");
+    }
+
+    #[test]
+    fn render_without_extension_or_counter() {
+        let template = RenameTemplate::default();
+        assert_eq!(template.render("a", None, &digest(), 1), "a-00ff");
+        assert_eq!(
+            template.render("a", Some("png"), &digest(), 1),
+            "a-00ff.png"
+        );
+        assert_eq!(
+            template.render("a", Some("png"), &digest(), 2),
+            "a_2-00ff.png"
+        );
+    }
+
+    #[test]
+    fn target_never_clobbers() {
+        let dir = std::env::temp_dir().join(format!("colek-rename-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.png");
+        fs::write(&path, b"a").unwrap();
+        fs::write(dir.join("a-00ff.png"), b"taken").unwrap();
+        fs::write(dir.join("a_2-00ff.png"), b"taken").unwrap();
+
+        let target = RenameTemplate::default().target(&path, &digest()).unwrap();
+        assert_eq!(target, dir.join("a_3-00ff.png"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
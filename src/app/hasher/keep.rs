@@ -0,0 +1,171 @@
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+use globset::{Glob, GlobMatcher};
+
+/// which file of a duplicate group survives, ties are always broken by path order
+#[derive(Debug, Clone)]
+pub enum KeepPolicy {
+    Oldest,
+    Newest,
+    ShortestPath,
+    LongestPath,
+    /// the file under the earliest scanned source directory
+    FirstSource,
+    /// the file matching the glob, `path-priority=<glob>`
+    PathPriority(GlobMatcher),
+}
+
+impl PartialEq for KeepPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::PathPriority(a), Self::PathPriority(b)) => a.glob() == b.glob(),
+            (a, b) => std::mem::discriminant(a) == std::mem::discriminant(b),
+        }
+    }
+}
+
+impl FromStr for KeepPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oldest" => Ok(Self::Oldest),
+            "newest" => Ok(Self::Newest),
+            "shortest-path" => Ok(Self::ShortestPath),
+            "longest-path" => Ok(Self::LongestPath),
+            "first-source" => Ok(Self::FirstSource),
+            s => match s.strip_prefix("path-priority=") {
+                Some(glob) => Glob::new(glob)
+                    .map(|x| Self::PathPriority(x.compile_matcher()))
+                    .map_err(|err| err.to_string()),
+                None => Err(format!(
+                    "invalid keep policy `{s}`, expected one of: oldest, newest, \
+                     shortest-path, longest-path, first-source, path-priority=<glob>"
+                )),
+            },
+        }
+    }
+}
+
+/// creation time, or modified time on filesystems without birth time
+fn file_time(path: &Path) -> Option<SystemTime> {
+    let metadata = path.metadata().ok()?;
+    metadata.created().or_else(|_| metadata.modified()).ok()
+}
+
+/// files with unknown time are never preferred
+fn cmp_time(a: Option<SystemTime>, b: Option<SystemTime>, newest: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) if newest => b.cmp(&a),
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+impl KeepPolicy {
+    /// `Less` when `a` should rather be kept than `b`
+    fn compare(&self, a: &Path, b: &Path, sources: &[PathBuf]) -> Ordering {
+        let source_index = |path: &Path| {
+            sources
+                .iter()
+                .enumerate()
+                .filter(|(_, source)| path.starts_with(source))
+                .max_by_key(|(_, source)| source.as_os_str().len())
+                .map_or(usize::MAX, |(i, _)| i)
+        };
+        let len = |path: &Path| path.as_os_str().len();
+        match self {
+            Self::Oldest => cmp_time(file_time(a), file_time(b), false),
+            Self::Newest => cmp_time(file_time(a), file_time(b), true),
+            Self::ShortestPath => len(a).cmp(&len(b)),
+            Self::LongestPath => len(b).cmp(&len(a)),
+            Self::FirstSource => source_index(a).cmp(&source_index(b)),
+            Self::PathPriority(glob) => glob.is_match(b).cmp(&glob.is_match(a)),
+        }
+    }
+
+    /// the path of `paths` that survive, `sources` are the scanned directories in order
+    pub fn keeper<'p>(&self, paths: &'p [PathBuf], sources: &[PathBuf]) -> Option<&'p PathBuf> {
+        paths
+            .iter()
+            .min_by(|a, b| self.compare(a, b, sources).then_with(|| a.cmp(b)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn keeper(policy: &str, candidates: &[&str], sources: &[&str]) -> Option<PathBuf> {
+        let policy: KeepPolicy = policy.parse().unwrap();
+        policy.keeper(&paths(candidates), &paths(sources)).cloned()
+    }
+
+    #[test]
+    fn ties_are_broken_by_path_order() {
+        let same_len = ["/b/x.png", "/a/x.png", "/c/x.png"];
+        assert_eq!(
+            keeper("shortest-path", &same_len, &[]),
+            Some("/a/x.png".into())
+        );
+        assert_eq!(
+            keeper("longest-path", &same_len, &[]),
+            Some("/a/x.png".into())
+        );
+        // missing files have no time, so every file ties
+        assert_eq!(
+            keeper("oldest", &["/nonexistent/b", "/nonexistent/a"], &[]),
+            Some("/nonexistent/a".into())
+        );
+        assert_eq!(
+            keeper("path-priority=**/keep/**", &["/b/keep/x", "/a/keep/x"], &[]),
+            Some("/a/keep/x".into())
+        );
+        assert_eq!(keeper("newest", &[], &[]), None);
+    }
+
+    #[test]
+    fn policies_pick_their_file() {
+        assert_eq!(
+            keeper("shortest-path", &["/a/long/x.png", "/b/x.png"], &[]),
+            Some("/b/x.png".into())
+        );
+        assert_eq!(
+            keeper("longest-path", &["/a/long/x.png", "/b/x.png"], &[]),
+            Some("/a/long/x.png".into())
+        );
+        assert_eq!(
+            keeper("path-priority=**/keep/**", &["/a/x", "/b/keep/x"], &[]),
+            Some("/b/keep/x".into())
+        );
+    }
+
+    #[test]
+    fn first_source_uses_the_deepest_matching_source() {
+        let sources = ["/b", "/a", "/a/sub"];
+        assert_eq!(
+            keeper("first-source", &["/a/x", "/b/x"], &sources),
+            Some("/b/x".into())
+        );
+        // `/a/sub` comes after `/a`, files outside every source come last
+        assert_eq!(
+            keeper("first-source", &["/a/sub/x", "/a/x", "/c/x"], &sources),
+            Some("/a/x".into())
+        );
+        assert_eq!(
+            keeper("first-source", &["/c/x", "/a/sub/x"], &sources),
+            Some("/a/sub/x".into())
+        );
+    }
+}
@@ -0,0 +1,497 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    path::PathBuf,
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+};
+
+use ignore::DirEntry;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    cache::{cached, CacheKey, CacheStage, HashCache},
+    digest::{
+        hash_file, hash_file_partial, same_contents, Digest, HashAlgorithm, PARTIAL_HASH_SIZE,
+    },
+    err_log,
+    known::{HashList, KnownHashes},
+};
+
+mod action;
+mod keep;
+mod quarantine;
+mod report;
+mod similar;
+
+pub use action::HasherEventDuplicate;
+pub use keep::KeepPolicy;
+pub use quarantine::restore;
+pub use report::ReportFormat;
+pub use similar::{AppSimilar, PerceptualHash};
+
+use action::{file_id, ActionContext, RenameTemplate};
+use quarantine::Quarantine;
+use report::{FileStatus, Report, ReportFile, ReportGroup};
+
+#[derive(Debug, Clone, PartialEq, clap::Args)]
+pub struct HashArgs {
+    /// on duplicate event
+    #[arg(short, long, default_value = "print")]
+    pub duplicate: HasherEventDuplicate,
+
+    /// maximum number of files hashed concurrently, defaults to the number of cpus
+    #[arg(short, long)]
+    pub jobs: Option<usize>,
+
+    /// which file of a duplicate group survives: oldest, newest, shortest-path,
+    /// longest-path, first-source or path-priority=<glob>
+    #[arg(short, long, default_value = "newest")]
+    pub keep: KeepPolicy,
+
+    /// directory the duplicates are moved into with `--duplicate quarantine`
+    #[arg(long, required_if_eq("duplicate", "quarantine"))]
+    pub quarantine: Option<PathBuf>,
+
+    /// send removed duplicates to the trash instead of deleting them, with `--duplicate remove`
+    #[arg(long)]
+    pub trash: bool,
+
+    /// new name of duplicates with `--duplicate rename`, placeholders: {stem}, {ext},
+    /// {hash} and {num}, the counter, which can also be written as `n` in braces
+    #[arg(long, default_value = "{stem}-{hash}.{ext}")]
+    pub rename_template: RenameTemplate,
+
+    /// write every duplicate group, with the keeper and the action taken, as a report
+    #[arg(long)]
+    pub report: Option<ReportFormat>,
+
+    /// file the report is written to, defaults to `duplicates.<format>`
+    #[arg(long, requires = "report")]
+    pub report_output: Option<PathBuf>,
+
+    /// hash every file again instead of reusing the hashes of unchanged files from earlier runs
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// report images that look alike, compared with this perceptual hash,
+    /// instead of files with identical contents
+    #[arg(long, conflicts_with_all = ["report", "keep", "no_cache", "rename_template"])]
+    pub similar: Option<PerceptualHash>,
+
+    /// maximum number of differing bits, out of 64, between two similar images
+    #[arg(long, default_value_t = 10, requires = "similar")]
+    pub distance: u32,
+
+    /// reference collection, a directory, a manifest or a `sha256sum` style hash list
+    /// of the `--algorithm` digests, scanned files already in it are reported and left
+    /// out of the duplicates
+    #[arg(long, conflicts_with = "similar")]
+    pub against: Option<HashList>,
+}
+
+impl HashArgs {
+    /// `--jobs`, defaults to the number of cpus
+    pub fn jobs(&self) -> usize {
+        self.jobs
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |x| x.get()))
+            .max(1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hashes {
+    hash: Digest,
+    size: u64,
+}
+
+/// group the values by key, only keeping the groups with more than one value
+fn collisions<K: Eq + Hash, V>(
+    items: impl IntoIterator<Item = (K, V)>,
+) -> impl Iterator<Item = (K, Vec<V>)> {
+    let mut groups: HashMap<K, Vec<V>> = HashMap::new();
+    for (key, value) in items {
+        groups.entry(key).or_default().push(value);
+    }
+    groups.into_iter().filter(|(_, values)| values.len() > 1)
+}
+
+/// files that all have the same contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    pub hashes: Hashes,
+    pub paths: Vec<PathBuf>,
+    /// distinct files among `paths`, the hard links of one file count once
+    pub files: usize,
+}
+
+impl DuplicateGroup {
+    fn new(hashes: Hashes, mut paths: Vec<PathBuf>) -> Self {
+        paths.sort();
+        // a file whose id can not be read counts as a file of its own
+        let files = paths
+            .iter()
+            .enumerate()
+            .map(|(i, path)| file_id(path).map_err(|_| i))
+            .collect::<HashSet<_>>()
+            .len();
+        Self {
+            hashes,
+            paths,
+            files,
+        }
+    }
+
+    /// bytes that would be reclaimed by keeping only one copy
+    #[inline]
+    pub fn wasted(&self) -> u64 {
+        self.hashes.size * (self.files as u64).saturating_sub(1)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AppHasher {
+    event_duplicate: Arc<HasherEventDuplicate>,
+    hashes: HashMap<Hashes, Vec<PathBuf>>,
+    algorithm: HashAlgorithm,
+    jobs: usize,
+    keep: KeepPolicy,
+    sources: Vec<PathBuf>,
+    ctx: ActionContext,
+    report: Option<(ReportFormat, PathBuf)>,
+    cache: Option<Arc<HashCache>>,
+    against: Option<Arc<KnownHashes>>,
+}
+
+impl AppHasher {
+    /// `sources` are the scanned directories, in order, and `cache` the hashes kept
+    /// between runs, `None` with `--no-cache`
+    pub fn new(
+        args: HashArgs,
+        algorithm: HashAlgorithm,
+        sources: Vec<PathBuf>,
+        cache: Option<Arc<HashCache>>,
+    ) -> crate::Result<Self> {
+        let jobs = args.jobs();
+        let ctx = ActionContext {
+            quarantine: args.quarantine.map(Quarantine::new).transpose()?,
+            trash: args.trash,
+            rename: args.rename_template,
+        };
+        let report = args.report.map(|format| {
+            let output = args
+                .report_output
+                .unwrap_or_else(|| PathBuf::from(format!("duplicates.{}", format.extension())));
+            (format, output)
+        });
+        let against = args
+            .against
+            .map(|list| KnownHashes::load(&list, algorithm))
+            .transpose()?;
+        // the reference is compared with the digests of the duplicate search
+        if let Some(known) = against.as_ref().filter(|x| x.algorithm() != algorithm) {
+            return Err(format!(
+                "`--against` is hashed with {}, run with `--algorithm {0}`",
+                known.algorithm()
+            )
+            .into());
+        }
+        let against = against.map(Arc::new);
+        Ok(Self {
+            event_duplicate: Arc::new(args.duplicate),
+            hashes: HashMap::new(),
+            algorithm,
+            jobs,
+            keep: args.keep,
+            sources,
+            ctx,
+            report,
+            cache,
+            against,
+        })
+    }
+
+    /// every group of duplicates, biggest waste first, paths sorted so the result is stable
+    pub fn groups(&self) -> Vec<DuplicateGroup> {
+        let mut groups: Vec<_> = self
+            .hashes
+            .iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(hashes, paths)| DuplicateGroup::new(*hashes, paths.clone()))
+            .collect();
+        groups.sort_by(|a, b| {
+            b.wasted()
+                .cmp(&a.wasted())
+                .then_with(|| a.paths.cmp(&b.paths))
+        });
+        groups
+    }
+
+    /// act on `group`, returning what was done to each of its files
+    pub fn on_duplicate(&self, group: &DuplicateGroup) -> crate::Result<Option<ReportGroup>> {
+        let Some(keep) = self.keep.keeper(&group.paths, &self.sources) else {
+            return Ok(None);
+        };
+        let hash = group.hashes.hash;
+        let mut report = ReportGroup {
+            hash: hash.to_string(),
+            size: group.hashes.size,
+            wasted: group.wasted(),
+            keep: keep.clone(),
+            files: vec![ReportFile::new(keep, FileStatus::Kept)],
+        };
+        use HasherEventDuplicate as EV;
+        if let EV::Print = *self.event_duplicate {
+            println!("==================== DUPLICATE ======================");
+            println!(
+                "{} copies of {} bytes, {} bytes wasted ({hash})",
+                group.paths.len(),
+                group.hashes.size,
+                group.wasted()
+            );
+            println!("=> {} (keep)", keep.display());
+            for path in group.paths.iter().filter(|x| *x != keep) {
+                println!("=> {}", path.display());
+                report.files.push(ReportFile::new(path, FileStatus::Listed));
+            }
+            println!();
+            return Ok(Some(report));
+        }
+
+        let keep_id = file_id(keep).ok();
+        for path in group.paths.iter().filter(|x| *x != keep) {
+            // metadata is read before the action, the file may be gone after it
+            let mut file = ReportFile::new(path, FileStatus::Done);
+            // the same file seen twice, through a hard link or another mount of it,
+            // acting on it would act on the kept file too
+            if keep_id.is_some() && file_id(path).ok() == keep_id {
+                if *self.event_duplicate == EV::Hardlink {
+                    log::info!(
+                        "Skipping '{}' - already a hard link of '{}'",
+                        path.display(),
+                        keep.display()
+                    );
+                    file.reason = Some("already a hard link of the kept file".to_owned());
+                } else {
+                    log::warn!(
+                        "Skipping '{}' - same file as '{}'",
+                        path.display(),
+                        keep.display()
+                    );
+                    file.reason = Some("same file as the kept file".to_owned());
+                }
+                file.status = FileStatus::Skipped;
+                report.files.push(file);
+                continue;
+            }
+            if self.event_duplicate.is_destructive() {
+                match same_contents(path, keep) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        log::warn!(
+                            "Skipping '{}' - same hash as '{}' but different contents",
+                            path.display(),
+                            keep.display()
+                        );
+                        file.status = FileStatus::Skipped;
+                        file.reason = Some("contents differ from the kept file".to_owned());
+                        report.files.push(file);
+                        continue;
+                    }
+                    Err(err) => {
+                        log::error!(
+                            "Skipping '{}' - failed to compare contents (Reason: {err})",
+                            path.display()
+                        );
+                        file.status = FileStatus::Skipped;
+                        file.reason = Some(format!("failed to compare contents: {err}"));
+                        report.files.push(file);
+                        continue;
+                    }
+                }
+            }
+            let r = self.event_duplicate.apply(&self.ctx, path, keep, &hash);
+            if let Err(ref err) = r {
+                file.status = FileStatus::Failed;
+                file.reason = Some(err.to_string());
+            }
+            err_log!(
+                r,
+                "{:?} duplicate '{}'",
+                self.event_duplicate,
+                path.display()
+            );
+            report.files.push(file);
+        }
+        Ok(Some(report))
+    }
+}
+
+impl super::App for AppHasher {
+    type Item = (Hashes, PathBuf);
+
+    fn name() -> &'static str {
+        "Hasher App"
+    }
+    fn on_blocking(&mut self, recver: Receiver<Self::Item>) -> crate::Result<()> {
+        log::debug!("on_blocking");
+        while let Ok((hashes, path)) = recver.recv() {
+            self.hashes.entry(hashes).or_default().push(path);
+        }
+        Ok(())
+    }
+
+    fn file_scan(&mut self, tx: Sender<Self::Item>, rx: Receiver<DirEntry>) -> crate::Result<()> {
+        log::debug!("file_scan");
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs)
+            .thread_name(|i| format!("hasher-{i}"))
+            .build()
+            .map_err(|err| err.to_string())?;
+        let algorithm = self.algorithm;
+        let cache = self.cache.clone();
+        let against = self.against.clone();
+        let spawn = move || {
+            let cache = cache.as_deref();
+            let full_hash = |(size, (path, key)): (u64, (PathBuf, Option<CacheKey>))| {
+                let hash = cached(cache, key, &path, CacheStage::Full, || {
+                    hash_file(&path, algorithm).map(|(hash, _)| hash)
+                });
+                match hash {
+                    Ok(hash) => {
+                        tx.send((Hashes { hash, size }, path)).ok();
+                    }
+                    Err(err) => {
+                        log::error!(
+                            "Failed to read the contents of file: '{}' - {err}",
+                            path.display()
+                        );
+                    }
+                }
+            };
+            // stage 1: a file with an unique size can never have a duplicate
+            let sizes = rx.into_iter().filter_map(|entry| match entry.metadata() {
+                Ok(metadata) => {
+                    let key = cache.and(CacheKey::new(&metadata, algorithm));
+                    Some((metadata.len(), (entry.into_path(), key)))
+                }
+                Err(err) => {
+                    log::error!(
+                        "Failed to get metadata of '{}' - {err}",
+                        entry.path().display()
+                    );
+                    None
+                }
+            });
+            // files of a size in the `--against` reference are all fully hashed right away,
+            // duplicates only ever share a size so they are still found among them
+            let (known, sizes): (Vec<_>, Vec<_>) = sizes.partition(|(size, _)| {
+                against
+                    .as_ref()
+                    .is_some_and(|against| against.may_contain_size(*size))
+            });
+            if !known.is_empty() {
+                log::info!("{} file(s) may be in the reference", known.len());
+                pool.install(|| known.into_par_iter().for_each(full_hash));
+            }
+
+            let candidates: Vec<_> = collisions(sizes)
+                .flat_map(|(size, files)| files.into_iter().map(move |file| (size, file)))
+                .collect();
+            log::info!(
+                "{} file(s) share their size with another file",
+                candidates.len()
+            );
+
+            // stage 2: hash only the head and the tail of same-size files
+            let partials: Vec<_> = pool.install(|| {
+                candidates
+                    .into_par_iter()
+                    .filter_map(|(size, (path, key))| {
+                        let partial = cached(cache, key, &path, CacheStage::Partial, || {
+                            hash_file_partial(&path, size, algorithm)
+                        });
+                        match partial {
+                            Ok(partial) => Some(((size, partial), (path, key))),
+                            Err(err) => {
+                                log::error!(
+                                    "Failed to read the contents of file: '{}' - {err}",
+                                    path.display()
+                                );
+                                None
+                            }
+                        }
+                    })
+                    .collect()
+            });
+
+            // stage 3: fully hash what still collide, small files are already hashed whole
+            let mut candidates = Vec::new();
+            for ((size, partial), files) in collisions(partials) {
+                if size <= PARTIAL_HASH_SIZE * 2 {
+                    let hashes = Hashes {
+                        hash: partial,
+                        size,
+                    };
+                    files.into_iter().for_each(|(path, _)| {
+                        tx.send((hashes, path)).ok();
+                    });
+                } else {
+                    candidates.extend(files.into_iter().map(|file| (size, file)));
+                }
+            }
+            log::info!("{} file(s) need to be fully hashed", candidates.len());
+            pool.install(|| candidates.into_par_iter().for_each(full_hash));
+            if let Some(cache) = cache {
+                err_log!(cache.save(), "Failed to save the hash cache");
+            }
+            drop(tx);
+        };
+        rayon::spawn(spawn);
+        Ok(())
+    }
+
+    fn on_finish(&mut self) -> crate::Result<()> {
+        log::debug!("finish");
+        let mut present = Vec::new();
+        if let Some(ref against) = self.against {
+            self.hashes.retain(|hashes, paths| {
+                let known = against.contains(&hashes.hash);
+                if known {
+                    present.append(paths);
+                }
+                !known
+            });
+            present.sort();
+            if !present.is_empty() {
+                println!("================= ALREADY PRESENT ==================");
+                for path in &present {
+                    println!("=> {}", path.display());
+                }
+                println!();
+            }
+        }
+        let groups = self.groups();
+        let mut reports = Vec::with_capacity(groups.len());
+        for group in &groups {
+            reports.extend(self.on_duplicate(group)?);
+        }
+        let files: usize = groups.iter().map(|x| x.paths.len()).sum();
+        let wasted: u64 = groups.iter().map(DuplicateGroup::wasted).sum();
+        println!("============= Finish Hashing =============");
+        println!("duplicate groups        : {}", groups.len());
+        println!("duplicate files         : {files}");
+        println!("wasted bytes            : {wasted}");
+        if self.against.is_some() {
+            println!("already present         : {}", present.len());
+        }
+        if let Some((format, ref output)) = self.report {
+            Report::new(self.algorithm, *self.event_duplicate, reports)
+                .write_file(format, output)?;
+            println!("report                  : {}", output.display());
+        }
+        Ok(())
+    }
+}
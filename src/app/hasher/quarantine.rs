@@ -0,0 +1,168 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::digest::Digest;
+
+/// file inside the quarantine directory recording every quarantined file
+pub const QUARANTINE_INDEX: &str = "index.jsonl";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuarantineRecord {
+    pub original: PathBuf,
+    /// relative to the quarantine directory, so it can be restored from any working directory
+    pub quarantined: PathBuf,
+    pub hash: String,
+    pub time: u64,
+}
+
+/// directory duplicates are moved into instead of being deleted
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quarantine {
+    dir: PathBuf,
+}
+
+impl Quarantine {
+    pub fn new(dir: PathBuf) -> crate::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// move `path` into the quarantine, keeping its original path below the quarantine dir
+    pub fn put(&self, path: &Path, hash: &Digest) -> io::Result<()> {
+        let relative: PathBuf = path
+            .components()
+            .filter(|x| matches!(x, Component::Normal(_)))
+            .collect();
+        let fname = relative
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?
+            .to_string_lossy()
+            .into_owned();
+        // a name taken by an earlier duplicate moves this one to the next numbered name
+        let mut names = crate::app::numbered_names(&fname).map(|x| relative.with_file_name(x));
+        let relative = loop {
+            let relative = names.next().expect("numbered names never end");
+            match move_file(path, &self.dir.join(&relative)) {
+                Ok(()) => break relative,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        };
+
+        let record = QuarantineRecord {
+            original: path.to_path_buf(),
+            quarantined: relative,
+            hash: hash.to_string(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs()),
+        };
+        let mut index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(QUARANTINE_INDEX))?;
+        writeln!(index, "{}", serde_json::to_string(&record)?)
+    }
+}
+
+/// rename, falling back to copy and remove when crossing filesystems,
+/// an existing `to` is never replaced
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if to.symlink_metadata().is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists", to.display()),
+        ));
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    io::copy(&mut fs::File::open(from)?, &mut fs::File::create_new(to)?)?;
+    fs::set_permissions(to, fs::metadata(from)?.permissions())?;
+    fs::remove_file(from)
+}
+
+/// put back every file recorded in the index of quarantine `dir`,
+/// files that can not be restored stay in the index
+pub fn restore(dir: &Path) -> crate::Result<()> {
+    let index = dir.join(QUARANTINE_INDEX);
+    let mut remaining = Vec::new();
+    let mut restored = 0usize;
+    for line in BufReader::new(fs::File::open(&index)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: QuarantineRecord = serde_json::from_str(&line)?;
+        if record.original.exists() {
+            log::warn!(
+                "Not restoring '{}' - original path already exists",
+                record.original.display()
+            );
+            remaining.push(line);
+            continue;
+        }
+        // indexes written before the paths were relative hold absolute paths, join keeps them
+        match move_file(&dir.join(&record.quarantined), &record.original) {
+            Ok(()) => {
+                log::info!("Restored '{}'", record.original.display());
+                restored += 1;
+            }
+            Err(err) => {
+                log::error!(
+                    "Failed to restore '{}' - (Reason: {err})",
+                    record.original.display()
+                );
+                remaining.push(line);
+            }
+        }
+    }
+
+    if remaining.is_empty() {
+        fs::remove_file(&index)?;
+    } else {
+        fs::write(&index, remaining.join("\n") + "\n")?;
+    }
+    println!(
+        "restored {restored} file(s), {} left in quarantine",
+        remaining.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn put_numbers_names_taken_by_earlier_duplicates() {
+        let root = std::env::temp_dir().join(format!("colek-quarantine-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let quarantine = Quarantine::new(root.join("q")).unwrap();
+        let path = root.join("src").join("a.txt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let digest = Digest::from_hex("00ff").unwrap();
+        for contents in ["1", "2", "3"] {
+            fs::write(&path, contents).unwrap();
+            quarantine.put(&path, &digest).unwrap();
+        }
+
+        let dir = quarantine
+            .dir
+            .join(root.join("src").strip_prefix("/").unwrap());
+        for (name, contents) in [("a.txt", "1"), ("a_1.txt", "2"), ("a_2.txt", "3")] {
+            assert_eq!(fs::read_to_string(dir.join(name)).unwrap(), contents);
+        }
+        assert!(!path.exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use serde::Serialize;
+
+use super::HasherEventDuplicate;
+use crate::{digest::HashAlgorithm, manifest::mtime_secs};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Html,
+}
+
+impl ReportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::Html => "html",
+        }
+    }
+}
+
+/// what happened to a file of a duplicate group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    /// the surviving file
+    Kept,
+    /// nothing was done, `--duplicate print`
+    Listed,
+    Done,
+    Skipped,
+    Failed,
+}
+
+impl FileStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Kept => "kept",
+            Self::Listed => "listed",
+            Self::Done => "done",
+            Self::Skipped => "skipped",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportFile {
+    pub path: PathBuf,
+    /// seconds since the unix epoch, taken before the action
+    pub mtime: Option<u64>,
+    pub created: Option<u64>,
+    pub status: FileStatus,
+    pub reason: Option<String>,
+}
+
+impl ReportFile {
+    pub fn new(path: &Path, status: FileStatus) -> Self {
+        let metadata = path.metadata().ok();
+        let created = metadata
+            .as_ref()
+            .and_then(|x| x.created().ok())
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok());
+        Self {
+            path: path.to_path_buf(),
+            mtime: metadata.as_ref().and_then(mtime_secs),
+            created: created.map(|x| x.as_secs()),
+            status,
+            reason: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportGroup {
+    pub hash: String,
+    pub size: u64,
+    pub wasted: u64,
+    pub keep: PathBuf,
+    pub files: Vec<ReportFile>,
+}
+
+/// every duplicate group with what was done about it, written with `--report`
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub algorithm: HashAlgorithm,
+    pub action: HasherEventDuplicate,
+    pub duplicate_groups: usize,
+    pub duplicate_files: usize,
+    /// bytes freed by keeping only one file of each group
+    pub reclaimable: u64,
+    pub groups: Vec<ReportGroup>,
+}
+
+/// one line of the csv report, a file with its group
+#[derive(Serialize)]
+struct ReportRow<'a> {
+    group: usize,
+    hash: &'a str,
+    size: u64,
+    path: &'a Path,
+    mtime: Option<u64>,
+    created: Option<u64>,
+    status: FileStatus,
+    reason: Option<&'a str>,
+}
+
+impl Report {
+    pub fn new(
+        algorithm: HashAlgorithm,
+        action: HasherEventDuplicate,
+        groups: Vec<ReportGroup>,
+    ) -> Self {
+        Self {
+            algorithm,
+            action,
+            duplicate_groups: groups.len(),
+            duplicate_files: groups.iter().map(|x| x.files.len()).sum(),
+            reclaimable: groups.iter().map(|x| x.wasted).sum(),
+            groups,
+        }
+    }
+
+    fn action_name(&self) -> String {
+        use clap::ValueEnum;
+        self.action
+            .to_possible_value()
+            .map_or_else(String::new, |x| x.get_name().to_owned())
+    }
+
+    pub fn write_file(&self, format: ReportFormat, path: &Path) -> crate::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        match format {
+            ReportFormat::Json => self.write_json(writer),
+            ReportFormat::Csv => self.write_csv(writer),
+            ReportFormat::Html => self.write_html(writer),
+        }
+    }
+
+    fn write_json(&self, mut writer: impl Write) -> crate::Result<()> {
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush().map_err(From::from)
+    }
+
+    /// the totals are written as `#` comment lines before the csv table
+    fn write_csv(&self, mut writer: impl Write) -> crate::Result<()> {
+        writeln!(writer, "# algorithm: {}", self.algorithm)?;
+        writeln!(writer, "# action: {}", self.action_name())?;
+        writeln!(writer, "# duplicate_groups: {}", self.duplicate_groups)?;
+        writeln!(writer, "# duplicate_files: {}", self.duplicate_files)?;
+        writeln!(writer, "# reclaimable: {}", self.reclaimable)?;
+        let mut csv = csv::Writer::from_writer(writer);
+        for (index, group) in self.groups.iter().enumerate() {
+            for file in &group.files {
+                csv.serialize(ReportRow {
+                    group: index,
+                    hash: &group.hash,
+                    size: group.size,
+                    path: &file.path,
+                    mtime: file.mtime,
+                    created: file.created,
+                    status: file.status,
+                    reason: file.reason.as_deref(),
+                })?;
+            }
+        }
+        csv.flush().map_err(From::from)
+    }
+
+    fn write_html(&self, mut w: impl Write) -> crate::Result<()> {
+        writeln!(w, "<!DOCTYPE html>")?;
+        writeln!(
+            w,
+            "<html><head><meta charset=\"utf-8\"><title>colek duplicates</title>"
+        )?;
+        writeln!(
+            w,
+            "<style>body{{font-family:sans-serif}}table{{border-collapse:collapse;margin-bottom:1em}}\
+             td,th{{border:1px solid #ccc;padding:2px 6px;text-align:left}}\
+             .kept{{background:#e6ffe6}}.skipped,.failed{{background:#ffe6e6}}</style>"
+        )?;
+        writeln!(w, "</head><body>")?;
+        writeln!(w, "<h1>Duplicates</h1><ul>")?;
+        writeln!(w, "<li>algorithm: {}</li>", self.algorithm)?;
+        writeln!(w, "<li>action: {}</li>", self.action_name())?;
+        writeln!(w, "<li>duplicate groups: {}</li>", self.duplicate_groups)?;
+        writeln!(w, "<li>duplicate files: {}</li>", self.duplicate_files)?;
+        writeln!(w, "<li>reclaimable bytes: {}</li></ul>", self.reclaimable)?;
+        for group in &self.groups {
+            writeln!(
+                w,
+                "<h2>{} copies of {} bytes, {} bytes wasted</h2><p><code>{}</code></p>",
+                group.files.len(),
+                group.size,
+                group.wasted,
+                group.hash
+            )?;
+            writeln!(
+                w,
+                "<table><tr><th>path</th><th>mtime</th><th>created</th><th>status</th><th>reason</th></tr>"
+            )?;
+            for file in &group.files {
+                let status = file.status.as_str();
+                let time = |x: Option<u64>| x.map(|x| x.to_string()).unwrap_or_default();
+                writeln!(
+                    w,
+                    "<tr class=\"{status}\"><td>{}</td><td>{}</td><td>{}</td><td>{status}</td><td>{}</td></tr>",
+                    escape_html(&file.path.display().to_string()),
+                    time(file.mtime),
+                    time(file.created),
+                    escape_html(file.reason.as_deref().unwrap_or_default())
+                )?;
+            }
+            writeln!(w, "</table>")?;
+        }
+        writeln!(w, "</body></html>")?;
+        w.flush().map_err(From::from)
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,270 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{Receiver, Sender},
+};
+
+use ignore::DirEntry;
+use image::{imageops::FilterType, GrayImage};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use super::{HashArgs, HasherEventDuplicate};
+use crate::filters::{detect_format, Filter};
+
+/// how images are reduced to 64 bits, close images have close hashes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PerceptualHash {
+    /// average hash, each pixel of a 8x8 thumbnail against the mean
+    Ahash,
+    /// difference hash, each pixel of a 9x8 thumbnail against its right neighbour
+    Dhash,
+    /// perceptual hash, the low frequencies of a 32x32 thumbnail against their median
+    Phash,
+}
+
+impl PerceptualHash {
+    fn thumbnail(path: &Path, width: u32, height: u32) -> Result<GrayImage, image::ImageError> {
+        let image = image::ImageReader::open(path)?
+            .with_guessed_format()?
+            .decode()?;
+        Ok(image
+            .resize_exact(width, height, FilterType::Triangle)
+            .to_luma8())
+    }
+
+    pub fn hash(self, path: &Path) -> Result<u64, image::ImageError> {
+        let bits = |pixels: &mut dyn Iterator<Item = bool>| {
+            pixels.fold(0u64, |acc, bit| (acc << 1) | bit as u64)
+        };
+        match self {
+            Self::Ahash => {
+                let thumb = Self::thumbnail(path, 8, 8)?;
+                let mean = thumb.pixels().map(|x| x[0] as u32).sum::<u32>() / 64;
+                Ok(bits(&mut thumb.pixels().map(|x| x[0] as u32 > mean)))
+            }
+            Self::Dhash => {
+                let thumb = Self::thumbnail(path, 9, 8)?;
+                let mut pixels = (0..8)
+                    .flat_map(|y| (0..8).map(move |x| (x, y)))
+                    .map(|(x, y)| thumb.get_pixel(x, y)[0] < thumb.get_pixel(x + 1, y)[0]);
+                Ok(bits(&mut pixels))
+            }
+            Self::Phash => {
+                let thumb = Self::thumbnail(path, 32, 32)?;
+                let coefficients = dct_low_frequencies(&thumb);
+                // the first coefficient is the average brightness, it does not describe the image
+                let mut sorted = coefficients[1..].to_vec();
+                sorted.sort_by(f64::total_cmp);
+                let median = sorted[sorted.len() / 2];
+                Ok(bits(&mut coefficients.iter().map(|x| *x > median)))
+            }
+        }
+    }
+}
+
+/// top-left 8x8 coefficients of the 2D DCT-II of a 32x32 image
+fn dct_low_frequencies(image: &GrayImage) -> [f64; 64] {
+    const N: usize = 32;
+    let cos: Vec<f64> = (0..8 * N)
+        .map(|i| {
+            let (k, n) = (i / N, i % N);
+            (std::f64::consts::PI / N as f64 * (n as f64 + 0.5) * k as f64).cos()
+        })
+        .collect();
+    let mut rows = [[0f64; 8]; N];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (k, value) in row.iter_mut().enumerate() {
+            *value = (0..N)
+                .map(|x| image.get_pixel(x as u32, y as u32)[0] as f64 * cos[k * N + x])
+                .sum();
+        }
+    }
+    let mut out = [0f64; 64];
+    for v in 0..8 {
+        for u in 0..8 {
+            out[v * 8 + u] = (0..N).map(|y| rows[y][u] * cos[v * N + y]).sum();
+        }
+    }
+    out
+}
+
+/// find the groups of images whose hashes differ by at most `distance` bits,
+/// two images are in the same group when a chain of close images link them
+fn clusters(hashes: &[(PathBuf, u64)], distance: u32) -> Vec<Vec<usize>> {
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for a in 0..hashes.len() {
+        for b in a + 1..hashes.len() {
+            if (hashes[a].1 ^ hashes[b].1).count_ones() <= distance {
+                let (ra, rb) = (root(&mut parent, a), root(&mut parent, b));
+                parent[ra.max(rb)] = ra.min(rb);
+            }
+        }
+    }
+    let mut groups: Vec<Vec<usize>> = vec![Vec::new(); hashes.len()];
+    for i in 0..hashes.len() {
+        let r = root(&mut parent, i);
+        groups[r].push(i);
+    }
+    groups.retain(|x| x.len() > 1);
+    groups
+}
+
+/// report images that look alike, even when resized or recompressed
+#[derive(Debug, Clone)]
+pub struct AppSimilar {
+    kind: PerceptualHash,
+    distance: u32,
+    jobs: usize,
+    hashes: Vec<(PathBuf, u64)>,
+}
+
+impl AppSimilar {
+    pub fn new(args: HashArgs, kind: PerceptualHash) -> crate::Result<Self> {
+        if args.duplicate != HasherEventDuplicate::Print {
+            return Err("`--similar` only reports the groups, use `--duplicate print`".into());
+        }
+        Ok(Self {
+            kind,
+            distance: args.distance,
+            jobs: args.jobs(),
+            hashes: Vec::new(),
+        })
+    }
+}
+
+impl crate::app::App for AppSimilar {
+    type Item = (PathBuf, u64);
+
+    fn name() -> &'static str {
+        "Similar Images App"
+    }
+
+    fn on_blocking(&mut self, recver: Receiver<Self::Item>) -> crate::Result<()> {
+        self.hashes.extend(recver);
+        Ok(())
+    }
+
+    fn file_scan(&mut self, tx: Sender<Self::Item>, rx: Receiver<DirEntry>) -> crate::Result<()> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs)
+            .thread_name(|i| format!("similar-{i}"))
+            .build()
+            .map_err(|err| err.to_string())?;
+        let kind = self.kind;
+        rayon::spawn(move || {
+            let images: Vec<_> = rx
+                .into_iter()
+                .map(DirEntry::into_path)
+                .filter(|path| detect_format(path).is_some_and(|x| Filter::Image.is_extension(&x)))
+                .collect();
+            log::info!("{} image(s) to decode", images.len());
+            pool.install(|| {
+                images
+                    .into_par_iter()
+                    .for_each(|path| match kind.hash(&path) {
+                        Ok(hash) => {
+                            tx.send((path, hash)).ok();
+                        }
+                        Err(err) => {
+                            log::warn!("Failed to decode image '{}' - {err}", path.display());
+                        }
+                    })
+            });
+            drop(tx);
+        });
+        Ok(())
+    }
+
+    fn on_finish(&mut self) -> crate::Result<()> {
+        self.hashes.sort();
+        let mut groups = clusters(&self.hashes, self.distance);
+        groups.sort_by_key(|x| std::cmp::Reverse(x.len()));
+        for group in &groups {
+            let (_, first) = self.hashes[group[0]];
+            println!("===================== SIMILAR =======================");
+            for &i in group {
+                let (ref path, hash) = self.hashes[i];
+                let distance = (hash ^ first).count_ones();
+                println!("=> {} ({hash:016x}, distance {distance})", path.display());
+            }
+            println!();
+        }
+        let files: usize = groups.iter().map(Vec::len).sum();
+        println!("============= Finish Comparing =============");
+        println!("images compared         : {}", self.hashes.len());
+        println!("similar groups          : {}", groups.len());
+        println!("similar images          : {files}");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashes(values: &[u64]) -> Vec<(PathBuf, u64)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (PathBuf::from(i.to_string()), *x))
+            .collect()
+    }
+
+    #[test]
+    fn clusters_link_chains_of_close_hashes() {
+        // 0 and 2 differ by 4 bits, they are linked through 1
+        let hashes = hashes(&[0b0000, 0b0011, 0b1111, u64::MAX, 0b0011]);
+        assert_eq!(clusters(&hashes, 2), vec![vec![0, 1, 2, 4]]);
+        assert_eq!(clusters(&hashes, 0), vec![vec![1, 4]]);
+        assert!(clusters(&hashes[..1], 64).is_empty());
+    }
+
+    #[test]
+    fn close_images_have_close_hashes() {
+        let dir = std::env::temp_dir().join(format!("colek-similar-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pattern = |x: u32, y: u32, size: u32| {
+            let (x, y) = (x * 64 / size, y * 64 / size);
+            let value = (x * 3 + y * 2) as u8 ^ if (x / 16 + y / 16) % 2 == 0 { 0 } else { 0x80 };
+            image::Luma([value])
+        };
+        let save = |name: &str, image: GrayImage| {
+            let path = dir.join(name);
+            image.save(&path).unwrap();
+            path
+        };
+        let original = save(
+            "original.png",
+            GrayImage::from_fn(64, 64, |x, y| pattern(x, y, 64)),
+        );
+        let resized = save(
+            "resized.png",
+            GrayImage::from_fn(96, 96, |x, y| pattern(x, y, 96)),
+        );
+        let mut inverted = image::open(&original).unwrap().to_luma8();
+        image::imageops::invert(&mut inverted);
+        let inverted = save("inverted.png", inverted);
+
+        for kind in [
+            PerceptualHash::Ahash,
+            PerceptualHash::Dhash,
+            PerceptualHash::Phash,
+        ] {
+            let hash = kind.hash(&original).unwrap();
+            let close = (hash ^ kind.hash(&resized).unwrap()).count_ones();
+            let far = (hash ^ kind.hash(&inverted).unwrap()).count_ones();
+            assert!(close <= 8, "{kind:?}: resized image is {close} bits away");
+            assert!(
+                far >= 24,
+                "{kind:?}: inverted image is only {far} bits away"
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,26 +1,48 @@
 mod app_zip;
 mod copy;
+mod dedupe;
 mod default;
 mod hasher;
+mod stats;
+mod store;
 
 use std::{
     fs::File,
     io::Read,
-    sync::mpsc::{channel, Receiver, Sender},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
     time::Instant,
 };
 
-pub use app_zip::AppZip;
+pub use app_zip::{AppZip, ZipPassword};
 pub use copy::AppCopy;
-pub use default::AppDefault;
-pub use hasher::{AppHasher, HasherEventDuplicate};
+pub use default::{AppDefault, StdoutField, StdoutFormat};
+pub use hasher::{restore, AppHasher, AppSimilar, HashArgs, HasherEventDuplicate};
 use ignore::{DirEntry, ParallelVisitor, ParallelVisitorBuilder, WalkState};
+use rayon::iter::{ParallelBridge, ParallelIterator};
+pub use stats::StatsFormat;
+pub use store::{checkout, AppStore};
 
 use crate::{
     filters::{contains_magic_bytes, Filter, Filters, MAGIC_BYTE_MAX_LEN},
+    known::KnownFilter,
     system::DiskPartition,
 };
 
+/// `fname` followed by `<stem>_1.<ext>`, `<stem>_2.<ext>`... to pick a free name from
+fn numbered_names(fname: &str) -> impl Iterator<Item = String> + '_ {
+    let (stem, ext) = match fname.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+        _ => (fname, None),
+    };
+    std::iter::once(fname.to_owned()).chain((1..).map(move |n| match ext {
+        Some(ext) => format!("{stem}_{n}.{ext}"),
+        None => format!("{stem}_{n}"),
+    }))
+}
+
 fn scans_directory(drives: Vec<DiskPartition>, tx: Sender<DirEntry>, filter: Filters) {
     log::debug!("Start Scanning directory");
     if drives.is_empty() {
@@ -42,6 +64,34 @@ fn scans_directory(drives: Vec<DiskPartition>, tx: Sender<DirEntry>, filter: Fil
     log::debug!("End Scanning directory");
 }
 
+/// pass on the scanned files accepted by the hash lists, hashed on a pool of
+/// `--jobs` threads instead of the walker threads
+fn filter_known(
+    known: Arc<KnownFilter>,
+    rx: Receiver<DirEntry>,
+    tx: Sender<DirEntry>,
+) -> crate::Result<()> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(known.jobs())
+        .thread_name(|i| format!("known-{i}"))
+        .build()
+        .map_err(|err| err.to_string())?;
+    // not on the global pool, whose worker waiting on `install` could pick up the consumer
+    std::thread::spawn(move || {
+        pool.install(|| {
+            rx.into_iter().par_bridge().for_each(|entry| {
+                if known.accepts(entry.path(), entry.metadata().ok().as_ref()) {
+                    tx.send(entry).ok();
+                } else {
+                    log::debug!("Filtered out by hash lists: '{}'", entry.path().display());
+                }
+            })
+        });
+        known.save_cache();
+    });
+    Ok(())
+}
+
 pub trait App {
     type Item;
 
@@ -55,12 +105,26 @@ pub trait App {
         Ok(())
     }
 
-    fn run(&mut self, drives: Vec<DiskPartition>, filter: Filters) -> crate::Result<()> {
+    /// `known` are the `--allowlist` and `--blocklist` the scanned files must pass
+    fn run(
+        &mut self,
+        drives: Vec<DiskPartition>,
+        filter: Filters,
+        known: Option<Arc<KnownFilter>>,
+    ) -> crate::Result<()> {
         log::info!("Running an App: {}", Self::name());
         let start = Instant::now();
 
         let (tx_walkdir, rx_walkdir) = channel();
         scans_directory(drives, tx_walkdir, filter);
+        let rx_walkdir = match known {
+            Some(known) => {
+                let (tx_known, rx_known) = channel();
+                filter_known(known, rx_walkdir, tx_known)?;
+                rx_known
+            }
+            None => rx_walkdir,
+        };
 
         let (tx_scanned, rx_scanned) = channel();
         self.file_scan(tx_scanned, rx_walkdir)?;
@@ -80,14 +144,16 @@ struct ParallelScan {
 }
 
 impl ParallelScan {
+    fn send(&self, entry: DirEntry) {
+        self.tx.send(entry).ok();
+    }
+
     fn visit_parallel(&mut self, entry: DirEntry) -> WalkState {
         match entry.path().extension().and_then(|x| {
             x.to_str()
                 .and_then(|ext| Filter::from_extension(ext.to_lowercase()))
         }) {
-            Some(filter_type) if self.filters.contains(filter_type) => {
-                self.tx.send(entry).ok();
-            }
+            Some(filter_type) if self.filters.contains(filter_type) => self.send(entry),
             Some(_) => {}
             None => {
                 let Ok(mut file) = File::open(entry.path()) else {
@@ -95,10 +161,10 @@ impl ParallelScan {
                 };
                 log::debug!("try matching magic bytes: {}", entry.path().display());
                 let mut buf = [0u8; MAGIC_BYTE_MAX_LEN];
-                file.read(&mut buf[..]).ok();
-                if contains_magic_bytes(buf) {
+                let n = file.read(&mut buf[..]).unwrap_or(0);
+                if contains_magic_bytes(&buf[..n]) {
                     log::debug!("Found magic bytes for: '{}'", entry.path().display());
-                    self.tx.send(entry).ok();
+                    self.send(entry);
                 }
             }
         }
@@ -0,0 +1,243 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{filters::Filter, manifest::civil_from_days, system::DiskPartition};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsFormat {
+    Text,
+    Json,
+}
+
+/// upper bounds of the size histogram buckets, the last bucket has none
+const SIZE_BUCKETS: &[(u64, &str)] = &[
+    (1 << 10, "< 1 KiB"),
+    (10 << 10, "1 KiB - 10 KiB"),
+    (100 << 10, "10 KiB - 100 KiB"),
+    (1 << 20, "100 KiB - 1 MiB"),
+    (10 << 20, "1 MiB - 10 MiB"),
+    (100 << 20, "10 MiB - 100 MiB"),
+    (1 << 30, "100 MiB - 1 GiB"),
+];
+const SIZE_BUCKET_LAST: &str = ">= 1 GiB";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Breakdown {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Breakdown {
+    fn add(&mut self, size: u64) {
+        self.files += 1;
+        self.bytes += size;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LargeFile {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeBucket {
+    pub range: &'static str,
+    #[serde(flatten)]
+    pub breakdown: Breakdown,
+}
+
+/// counts and bytes of the scanned files, broken down in every way the report shows
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanStats {
+    pub total: Breakdown,
+    pub categories: BTreeMap<String, Breakdown>,
+    pub extensions: BTreeMap<String, Breakdown>,
+    pub drives: BTreeMap<String, Breakdown>,
+    /// first directory below the mount point of the drive
+    pub directories: BTreeMap<PathBuf, Breakdown>,
+    pub largest: Vec<LargeFile>,
+    pub sizes: Vec<SizeBucket>,
+    /// by year of modification
+    pub years: BTreeMap<String, Breakdown>,
+    #[serde(skip)]
+    top: usize,
+    #[serde(skip)]
+    heap: BinaryHeap<Reverse<(u64, PathBuf)>>,
+}
+
+impl ScanStats {
+    /// keep the `top` largest files
+    pub fn new(top: usize) -> Self {
+        let mut sizes: Vec<_> = SIZE_BUCKETS.iter().map(|(_, range)| *range).collect();
+        sizes.push(SIZE_BUCKET_LAST);
+        Self {
+            total: Breakdown::default(),
+            categories: BTreeMap::new(),
+            extensions: BTreeMap::new(),
+            drives: BTreeMap::new(),
+            directories: BTreeMap::new(),
+            largest: Vec::new(),
+            sizes: sizes
+                .into_iter()
+                .map(|range| SizeBucket {
+                    range,
+                    breakdown: Breakdown::default(),
+                })
+                .collect(),
+            years: BTreeMap::new(),
+            top,
+            heap: BinaryHeap::with_capacity(top + 1),
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        drives: &[DiskPartition],
+        path: &Path,
+        size: u64,
+        mtime: Option<u64>,
+        format: Option<&str>,
+    ) {
+        self.total.add(size);
+        let category = format
+            .and_then(Filter::from_extension)
+            .map_or_else(|| "unknown".to_owned(), |x| x.to_string().to_lowercase());
+        self.categories.entry(category).or_default().add(size);
+        let extension = path.extension().map_or_else(
+            || "(none)".to_owned(),
+            |x| x.to_string_lossy().to_lowercase(),
+        );
+        self.extensions.entry(extension).or_default().add(size);
+
+        let drive = DiskPartition::find(drives, path);
+        let name = drive.map_or_else(|| "unknown".to_owned(), |x| x.name.clone());
+        self.drives.entry(name).or_default().add(size);
+        let relative = drive.and_then(|x| path.strip_prefix(&x.path).ok());
+        let directory = match relative.map(Path::components).and_then(|mut x| {
+            let first = x.next()?;
+            // a file right at the mount point has no directory
+            x.next().map(|_| first)
+        }) {
+            Some(Component::Normal(dir)) => drive.map_or_else(PathBuf::new, |x| x.path.join(dir)),
+            _ => drive.map_or_else(PathBuf::new, |x| x.path.clone()),
+        };
+        self.directories.entry(directory).or_default().add(size);
+
+        let bucket = SIZE_BUCKETS
+            .iter()
+            .position(|(max, _)| size < *max)
+            .unwrap_or(SIZE_BUCKETS.len());
+        self.sizes[bucket].breakdown.add(size);
+        let year = mtime.map_or_else(
+            || "unknown".to_owned(),
+            |x| civil_from_days((x / 86_400) as i64).0.to_string(),
+        );
+        self.years.entry(year).or_default().add(size);
+
+        if self.top > 0 {
+            self.heap.push(Reverse((size, path.to_path_buf())));
+            if self.heap.len() > self.top {
+                self.heap.pop();
+            }
+        }
+    }
+
+    /// move the largest files out of the heap, biggest first
+    pub fn finish(&mut self) {
+        let mut largest: Vec<_> = std::mem::take(&mut self.heap)
+            .into_iter()
+            .map(|Reverse((size, path))| LargeFile { path, size })
+            .collect();
+        largest.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        self.largest = largest;
+    }
+
+    pub fn write(&self, format: StatsFormat, mut writer: impl Write) -> crate::Result<()> {
+        match format {
+            StatsFormat::Json => {
+                serde_json::to_writer_pretty(&mut writer, self)?;
+                writeln!(writer)?;
+            }
+            StatsFormat::Text => self.write_text(&mut writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_text(&self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "============= Scan Statistics =============")?;
+        writeln!(w, "files scanned           : {}", self.total.files)?;
+        writeln!(
+            w,
+            "bytes scanned           : {} ({})",
+            self.total.bytes,
+            human_size(self.total.bytes)
+        )?;
+        // biggest first, as that is what is looked for
+        let section = |w: &mut dyn Write, title: &str, rows: Vec<(String, Breakdown)>| {
+            writeln!(w, "\n--- {title} ---")?;
+            for (name, x) in rows {
+                writeln!(
+                    w,
+                    "{name:<40} {:>8} files {:>12}",
+                    x.files,
+                    human_size(x.bytes)
+                )?;
+            }
+            io::Result::Ok(())
+        };
+        let sorted = |map: Vec<(String, Breakdown)>| {
+            let mut map = map;
+            map.sort_by(|(a, x), (b, y)| y.bytes.cmp(&x.bytes).then_with(|| a.cmp(b)));
+            map
+        };
+        let rows = |map: &BTreeMap<String, Breakdown>| {
+            sorted(map.iter().map(|(k, v)| (k.clone(), *v)).collect())
+        };
+        section(w, "categories", rows(&self.categories))?;
+        section(w, "extensions", rows(&self.extensions))?;
+        section(w, "drives", rows(&self.drives))?;
+        let directories = self
+            .directories
+            .iter()
+            .map(|(k, v)| (k.display().to_string(), *v))
+            .collect();
+        section(w, "top-level directories", sorted(directories))?;
+        let sizes = self
+            .sizes
+            .iter()
+            .map(|x| (x.range.to_owned(), x.breakdown))
+            .collect();
+        section(w, "sizes", sizes)?;
+        let years = self.years.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        section(w, "years", years)?;
+        if !self.largest.is_empty() {
+            writeln!(w, "\n--- {} largest files ---", self.largest.len())?;
+            for x in &self.largest {
+                writeln!(w, "{:>12} {}", human_size(x.size), x.path.display())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{size:.2} {}", UNITS[unit]),
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, Metadata, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ignore::DirEntry;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    digest::{hash_file, same_contents, Digest, HashAlgorithm, HashReader},
+    manifest::{civil_from_days, mtime_secs, ManifestEntry},
+    system::{DiskPartition, SystemDiskInfo},
+};
+
+/// settings of a store, written when it is created
+pub const STORE_CONFIG: &str = "store.json";
+/// every collected file, one json record per line
+pub const STORE_INDEX: &str = "index.jsonl";
+const STORE_OBJECTS: &str = "objects";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct StoreConfig {
+    algorithm: HashAlgorithm,
+}
+
+/// a collected file, `dest` of the entry is the object holding its contents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoreRecord {
+    pub host: Option<String>,
+    /// seconds since the unix epoch
+    pub collected: u64,
+    #[serde(flatten)]
+    pub entry: ManifestEntry,
+}
+
+fn read_index(dir: &Path) -> crate::Result<Vec<StoreRecord>> {
+    let file = match File::open(dir.join(STORE_INDEX)) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            records.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(records)
+}
+
+/// `objects/ab/cdef…` for the digest `abcdef…`
+fn object_path(hash: &Digest) -> String {
+    let hex = hash.to_string();
+    format!("{STORE_OBJECTS}/{}/{}", &hex[..2], &hex[2..])
+}
+
+/// collect files into a content-addressed store, contents already stored
+/// by any machine are never written twice
+pub struct AppStore {
+    dir: Arc<Path>,
+    algorithm: HashAlgorithm,
+    host: Option<String>,
+    drives: Arc<[DiskPartition]>,
+    /// (source, size, mtime) of the files already collected from this host
+    known: Arc<HashMap<PathBuf, (u64, Option<u64>)>>,
+    index: Option<BufWriter<File>>,
+    stored: usize,
+    shared: usize,
+}
+
+impl AppStore {
+    pub fn new(
+        dir: PathBuf,
+        sys: &SystemDiskInfo,
+        algorithm: HashAlgorithm,
+    ) -> crate::Result<Self> {
+        fs::create_dir_all(dir.join(STORE_OBJECTS))?;
+        let config = dir.join(STORE_CONFIG);
+        if config.exists() {
+            let recorded: StoreConfig = serde_json::from_reader(File::open(&config)?)?;
+            if recorded.algorithm != algorithm {
+                return Err(format!(
+                    "store '{}' is hashed with {}, run with `--algorithm {1}`",
+                    dir.display(),
+                    recorded.algorithm
+                )
+                .into());
+            }
+        } else {
+            serde_json::to_writer_pretty(File::create(&config)?, &StoreConfig { algorithm })?;
+        }
+        let host = sys.host_name.clone();
+        let known = read_index(&dir)?
+            .into_iter()
+            .filter(|x| x.host == host)
+            .map(|x| (x.entry.source, (x.entry.size, x.entry.mtime)))
+            .collect();
+        let index = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(STORE_INDEX))?;
+        Ok(Self {
+            dir: dir.into(),
+            algorithm,
+            host,
+            drives: sys.drives.clone().into(),
+            known: Arc::new(known),
+            index: Some(BufWriter::new(index)),
+            stored: 0,
+            shared: 0,
+        })
+    }
+
+    /// object holding the contents of `source`, `true` when it had to be written
+    fn store_file(
+        dir: &Path,
+        source: &Path,
+        algorithm: HashAlgorithm,
+    ) -> io::Result<(Digest, bool)> {
+        let (hash, _) = hash_file(source, algorithm)?;
+        let object = dir.join(object_path(&hash));
+        if object.exists() {
+            return Ok((hash, false));
+        }
+        if let Some(parent) = object.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // written next to the object then renamed, a partial object never exists,
+        // the name is unique so two stores of the same contents never share it
+        static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let mut tmp = object.clone().into_os_string();
+        tmp.push(format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let tmp = PathBuf::from(tmp);
+        let mut reader = HashReader::new(BufReader::new(File::open(source)?), algorithm);
+        let written = File::create_new(&tmp).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            io::copy(&mut reader, &mut writer)?;
+            writer.flush()
+        });
+        if let Err(err) = written {
+            fs::remove_file(&tmp).ok();
+            return Err(err);
+        }
+        if reader.digest() != hash {
+            fs::remove_file(&tmp).ok();
+            return Err(io::Error::other("file changed while being stored"));
+        }
+        fs::rename(&tmp, &object)?;
+        // objects are shared by every source with these contents, never edited
+        let mut permissions = fs::metadata(&object)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&object, permissions)?;
+        Ok((hash, true))
+    }
+}
+
+impl super::App for AppStore {
+    type Item = (ManifestEntry, bool);
+
+    fn name() -> &'static str {
+        "Store"
+    }
+
+    fn on_blocking(&mut self, rx: Receiver<Self::Item>) -> crate::Result<()> {
+        let collected = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        let index = self.index.as_mut().ok_or("store index already closed")?;
+        while let Ok((mut entry, written)) = rx.recv() {
+            if let Some(drive) = DiskPartition::find(&self.drives, &entry.source) {
+                entry.drive_name = drive.name.clone();
+                entry.drive_type = drive.tp;
+            }
+            let record = StoreRecord {
+                host: self.host.clone(),
+                collected,
+                entry,
+            };
+            serde_json::to_writer(&mut *index, &record)?;
+            writeln!(index)?;
+            match written {
+                true => self.stored += 1,
+                false => self.shared += 1,
+            }
+        }
+        Ok(())
+    }
+
+    fn file_scan(&mut self, tx: Sender<Self::Item>, rx: Receiver<DirEntry>) -> crate::Result<()> {
+        let dir = self.dir.clone();
+        let known = self.known.clone();
+        let algorithm = self.algorithm;
+        rayon::spawn(move || {
+            while let Ok(file) = rx.recv() {
+                let path = file.path();
+                let metadata: Metadata = match file.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(err) => {
+                        log::error!("Failed to get metadata of '{}' - {err}", path.display());
+                        continue;
+                    }
+                };
+                if known.get(path) == Some(&(metadata.len(), mtime_secs(&metadata))) {
+                    log::debug!("Skip unchanged file: '{}'", path.display());
+                    continue;
+                }
+                match Self::store_file(&dir, path, algorithm) {
+                    Ok((hash, written)) => {
+                        let entry = ManifestEntry::new(
+                            path,
+                            &metadata,
+                            hash.to_string(),
+                            object_path(&hash),
+                        );
+                        tx.send((entry, written)).ok();
+                    }
+                    Err(err) => {
+                        log::error!("Failed to store '{}' - {err}", path.display());
+                    }
+                }
+            }
+            drop(tx);
+        });
+        Ok(())
+    }
+
+    fn on_finish(&mut self) -> crate::Result<()> {
+        if let Some(mut index) = self.index.take() {
+            index.flush()?;
+        }
+        println!("============= Finish Storing =============");
+        println!("store                   : {}", self.dir.display());
+        println!("new objects             : {}", self.stored);
+        println!("already stored          : {}", self.shared);
+        Ok(())
+    }
+}
+
+/// relative path of a record in a checkout, with the `{host}`, `{drive}`, `{path}`,
+/// `{name}`, `{stem}`, `{ext}`, `{hash}`, `{year}`, `{month}` and `{day}` placeholders
+fn render_template(template: &str, record: &StoreRecord) -> PathBuf {
+    let entry = &record.entry;
+    let source: PathBuf = entry
+        .source
+        .components()
+        .filter(|x| matches!(x, Component::Normal(_)))
+        .collect();
+    let os = |x: Option<&std::ffi::OsStr>| x.map(|x| x.to_string_lossy().into_owned());
+    let (year, month, day) =
+        entry
+            .mtime
+            .map_or(("unknown".into(), "00".into(), "00".into()), |x| {
+                let (year, month, day) = civil_from_days((x / 86_400) as i64);
+                (year.to_string(), format!("{month:02}"), format!("{day:02}"))
+            });
+    let rendered = template
+        .replace("{host}", record.host.as_deref().unwrap_or("unknown"))
+        .replace("{drive}", entry.drive_name.trim_start_matches('/'))
+        .replace("{path}", &source.to_string_lossy())
+        .replace("{name}", &os(source.file_name()).unwrap_or_default())
+        .replace("{stem}", &os(source.file_stem()).unwrap_or_default())
+        .replace("{ext}", &os(source.extension()).unwrap_or_default())
+        .replace("{hash}", &entry.hash)
+        .replace("{year}", &year)
+        .replace("{month}", &month)
+        .replace("{day}", &day);
+    // never escape the checkout directory
+    Path::new(&rendered)
+        .components()
+        .filter(|x| matches!(x, Component::Normal(_)))
+        .collect()
+}
+
+/// copy of an object as a new file, writable unlike the object
+fn copy(object: &Path, target: &Path) -> io::Result<()> {
+    io::copy(&mut File::open(object)?, &mut File::create_new(target)?).map(|_| ())
+}
+
+/// materialise the objects of store `dir` into `dest` with `template` paths, copied so
+/// editing a file never changes the store, or with `hardlink` hard linked when possible
+pub fn checkout(dir: &Path, dest: &Path, template: &str, hardlink: bool) -> crate::Result<()> {
+    if !dir.join(STORE_CONFIG).exists() {
+        return Err(format!("'{}' is not a colek store", dir.display()).into());
+    }
+    // the last record of a source wins, it is its latest collection
+    let mut latest: HashMap<(Option<String>, PathBuf), StoreRecord> = HashMap::new();
+    for record in read_index(dir)? {
+        latest.insert((record.host.clone(), record.entry.source.clone()), record);
+    }
+    let mut records: Vec<_> = latest.into_values().collect();
+    records.sort_by(|a, b| (&a.host, &a.entry.source).cmp(&(&b.host, &b.entry.source)));
+
+    let (mut done, mut present, mut failed) = (0usize, 0usize, 0usize);
+    for record in records {
+        let object = dir.join(&record.entry.dest);
+        let target = dest.join(render_template(template, &record));
+        let fname = target
+            .file_name()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        // a name already holding this object is left as is, so checkout can be repeated
+        let free = super::numbered_names(&fname)
+            .map(|x| target.with_file_name(x))
+            .map(|x| match x.exists() {
+                false => Ok(Some(x)),
+                true => same_contents(&object, &x).map(|same| (!same).then_some(x)),
+            })
+            .find(|x| !matches!(x, Ok(Some(ref x)) if x.exists()))
+            .expect("should never fail");
+        let r = free.and_then(|target| match target {
+            None => Ok(false),
+            Some(target) => target
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| match hardlink {
+                    true => fs::hard_link(&object, &target).or_else(|_| copy(&object, &target)),
+                    false => copy(&object, &target),
+                })
+                .map(|_| true),
+        });
+        match r {
+            Ok(true) => done += 1,
+            Ok(false) => present += 1,
+            Err(err) => {
+                log::error!(
+                    "Failed to checkout '{}' - (Reason: {err})",
+                    target.display()
+                );
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "checked out {done} file(s) into '{}', {present} already there, {failed} failed",
+        dest.display()
+    );
+    Ok(())
+}
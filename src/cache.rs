@@ -0,0 +1,225 @@
+//! Hashes kept between runs, so unchanged files are not read again.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, Metadata},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::digest::{Digest, HashAlgorithm};
+
+/// file inside the colek cache directory holding the hashes
+pub const HASH_CACHE_FILE: &str = "hashes.jsonl";
+
+/// identity of the contents of a file, any write change the mtime or the inode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CacheKey {
+    pub dev: u64,
+    pub ino: u64,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub algorithm: HashAlgorithm,
+}
+
+impl CacheKey {
+    /// `None` on platforms without inode, those files are never cached
+    #[cfg(unix)]
+    pub fn new(metadata: &Metadata, algorithm: HashAlgorithm) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        Some(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            algorithm,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(_metadata: &Metadata, _algorithm: HashAlgorithm) -> Option<Self> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    #[serde(flatten)]
+    pub key: CacheKey,
+    /// last path the file was seen at, used when pruning
+    pub path: PathBuf,
+    pub partial: Option<Digest>,
+    pub full: Option<Digest>,
+}
+
+/// which stage of the duplicate search a digest belong to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStage {
+    Partial,
+    Full,
+}
+
+/// hash with `hash`, or reuse the digest remembered by `cache` for `key`
+pub fn cached(
+    cache: Option<&HashCache>,
+    key: Option<CacheKey>,
+    path: &Path,
+    stage: CacheStage,
+    hash: impl FnOnce() -> io::Result<Digest>,
+) -> io::Result<Digest> {
+    match (cache, key) {
+        (Some(cache), Some(key)) => cache.get_or_hash(key, path, stage, hash),
+        _ => hash(),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<CacheKey, CacheEntry>,
+    dirty: bool,
+}
+
+/// shared by the hashing threads, every access lock the entries
+#[derive(Debug)]
+pub struct HashCache {
+    file: PathBuf,
+    entries: Mutex<Entries>,
+}
+
+impl HashCache {
+    /// `hashes.jsonl` in the user cache directory
+    pub fn default_path() -> crate::Result<PathBuf> {
+        let dir = dirs::cache_dir().ok_or("no cache directory on this platform")?;
+        Ok(dir.join(crate::APP_NAME).join(HASH_CACHE_FILE))
+    }
+
+    /// load the cache from `file`, a missing file is an empty cache
+    /// and unreadable lines are dropped
+    pub fn open(file: PathBuf) -> crate::Result<Self> {
+        let mut entries = HashMap::new();
+        match File::open(&file) {
+            Ok(reader) => {
+                for line in BufReader::new(reader).lines() {
+                    match serde_json::from_str::<CacheEntry>(&line?) {
+                        Ok(entry) => {
+                            entries.insert(entry.key, entry);
+                        }
+                        Err(err) => log::warn!("Dropping invalid hash cache entry - {err}"),
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        Ok(Self {
+            file,
+            entries: Mutex::new(Entries {
+                map: entries,
+                dirty: false,
+            }),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Entries> {
+        // the entries stay consistent even when a hashing thread panicked
+        self.entries.lock().unwrap_or_else(|x| x.into_inner())
+    }
+
+    /// digest of the file at `path` from the cache when `key` is known,
+    /// otherwise computed with `hash` and remembered
+    pub fn get_or_hash(
+        &self,
+        key: CacheKey,
+        path: &Path,
+        stage: CacheStage,
+        hash: impl FnOnce() -> io::Result<Digest>,
+    ) -> io::Result<Digest> {
+        let cached = self.lock().map.get(&key).and_then(|x| match stage {
+            CacheStage::Partial => x.partial,
+            CacheStage::Full => x.full,
+        });
+        if let Some(digest) = cached {
+            return Ok(digest);
+        }
+        let digest = hash()?;
+        let mut entries = self.lock();
+        entries.dirty = true;
+        let entry = entries.map.entry(key).or_insert_with(|| CacheEntry {
+            key,
+            path: path.to_path_buf(),
+            partial: None,
+            full: None,
+        });
+        entry.path = path.to_path_buf();
+        match stage {
+            CacheStage::Partial => entry.partial = Some(digest),
+            CacheStage::Full => entry.full = Some(digest),
+        }
+        Ok(digest)
+    }
+
+    /// write the cache back if it changed, through a temporary file so a crash never truncate it
+    pub fn save(&self) -> crate::Result<()> {
+        let mut entries = self.lock();
+        if !entries.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = self.file.with_extension("jsonl.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for entry in entries.map.values() {
+            serde_json::to_writer(&mut writer, entry)?;
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp, &self.file)?;
+        entries.dirty = false;
+        Ok(())
+    }
+
+    /// drop the entries whose file is gone or changed since it was hashed,
+    /// returning how many were removed
+    pub fn prune(&self) -> usize {
+        let mut entries = self.lock();
+        let before = entries.map.len();
+        entries.map.retain(|key, entry| {
+            entry
+                .path
+                .metadata()
+                .ok()
+                .and_then(|x| CacheKey::new(&x, key.algorithm))
+                .is_some_and(|x| x == *key)
+        });
+        let removed = before - entries.map.len();
+        entries.dirty |= removed > 0;
+        removed
+    }
+
+    pub fn print_stats(&self) {
+        let entries = self.lock();
+        let mut algorithms: HashMap<HashAlgorithm, usize> = HashMap::new();
+        for key in entries.map.keys() {
+            *algorithms.entry(key.algorithm).or_default() += 1;
+        }
+        let full = entries.map.values().filter(|x| x.full.is_some()).count();
+        let bytes = fs::metadata(&self.file).map_or(0, |x| x.len());
+        println!("============= Hash Cache =============");
+        println!("file                    : {}", self.file.display());
+        println!("size on disk            : {bytes}");
+        println!("entries                 : {}", entries.map.len());
+        println!("fully hashed            : {full}");
+        let mut algorithms: Vec<_> = algorithms.into_iter().collect();
+        algorithms.sort_by_key(|(x, _)| x.to_string());
+        for (algorithm, count) in algorithms {
+            println!("{:<24}: {count}", algorithm.to_string());
+        }
+    }
+}
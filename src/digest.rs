@@ -0,0 +1,287 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use sha1::Digest as _;
+use xxhash_rust::xxh3::Xxh3;
+
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Xxh3,
+    Sha256,
+    Sha1,
+    Md5,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// number of bytes of the digests of this algorithm
+    pub const fn digest_len(self) -> usize {
+        match self {
+            Self::Xxh3 | Self::Md5 => 16,
+            Self::Sha1 => 20,
+            Self::Sha256 | Self::Blake3 => 32,
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Xxh3 => Hasher::Xxh3(Box::default()),
+            Self::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Self::Sha1 => Hasher::Sha1(sha1::Sha1::new()),
+            Self::Md5 => Hasher::Md5(md5::Md5::new()),
+            Self::Blake3 => Hasher::Blake3(Box::default()),
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Xxh3 => f.write_str("xxh3"),
+            Self::Sha256 => f.write_str("sha256"),
+            Self::Sha1 => f.write_str("sha1"),
+            Self::Md5 => f.write_str("md5"),
+            Self::Blake3 => f.write_str("blake3"),
+        }
+    }
+}
+
+/// incremental hasher for every [`HashAlgorithm`]
+#[derive(Clone)]
+pub enum Hasher {
+    Xxh3(Box<Xxh3>),
+    Sha256(sha2::Sha256),
+    Sha1(sha1::Sha1),
+    Md5(md5::Md5),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Xxh3(h) => h.update(bytes),
+            Self::Sha256(h) => h.update(bytes),
+            Self::Sha1(h) => h.update(bytes),
+            Self::Md5(h) => h.update(bytes),
+            Self::Blake3(h) => {
+                h.update(bytes);
+            }
+        }
+    }
+
+    pub fn digest(&self) -> Digest {
+        match self {
+            Self::Xxh3(h) => Digest::new(&h.digest128().to_be_bytes()),
+            Self::Sha256(h) => Digest::new(&h.clone().finalize()),
+            Self::Sha1(h) => Digest::new(&h.clone().finalize()),
+            Self::Md5(h) => Digest::new(&h.clone().finalize()),
+            Self::Blake3(h) => Digest::new(h.finalize().as_bytes()),
+        }
+    }
+}
+
+/// output of a [`Hasher`], big enough for the widest algorithm (32 bytes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Digest {
+    bytes: [u8; Digest::MAX_LEN],
+    len: u8,
+}
+
+impl Digest {
+    pub const MAX_LEN: usize = 32;
+
+    fn new(bytes: &[u8]) -> Self {
+        let mut digest = Self {
+            bytes: [0; Self::MAX_LEN],
+            len: bytes.len() as u8,
+        };
+        digest.bytes[..bytes.len()].copy_from_slice(bytes);
+        digest
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    /// parse the lowercase or uppercase hex written by [`Display`]
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        // `from_str_radix` alone would take a sign
+        if !hex.len().is_multiple_of(2)
+            || hex.len() > Self::MAX_LEN * 2
+            || !hex.bytes().all(|x| x.is_ascii_hexdigit())
+        {
+            return None;
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self::new(&bytes))
+    }
+}
+
+impl Serialize for Digest {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        Self::from_hex(&hex).ok_or_else(|| serde::de::Error::custom("invalid hex digest"))
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_bytes()
+            .iter()
+            .try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+/// reader adapter that hash all the bytes read through it
+pub struct HashReader<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R: Read> HashReader<R> {
+    pub fn new(inner: R, algorithm: HashAlgorithm) -> Self {
+        Self {
+            inner,
+            hasher: algorithm.hasher(),
+        }
+    }
+
+    /// digest of all bytes read so far
+    #[inline]
+    pub fn digest(&self) -> Digest {
+        self.hasher.digest()
+    }
+}
+
+impl<R: Read> Read for HashReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// size of the buffer each file is streamed through while hashing
+pub const HASH_BUFFER_SIZE: usize = 256 << 10;
+
+/// hash the contents of file through a fixed-size buffer,
+/// so memory does not grow with the size of the file
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<(Digest, u64)> {
+    let file = File::open(path)?;
+    let mut reader = HashReader::new(BufReader::with_capacity(HASH_BUFFER_SIZE, file), algorithm);
+    let size = io::copy(&mut reader, &mut io::sink())?;
+    Ok((reader.digest(), size))
+}
+
+/// bytes hashed from both the head and the tail of a file in the partial hash stage
+pub const PARTIAL_HASH_SIZE: u64 = 4 << 10;
+
+/// hash only the first and the last [`PARTIAL_HASH_SIZE`] bytes of file with `size`,
+/// files smaller than both of them together are hashed whole
+pub fn hash_file_partial(path: &Path, size: u64, algorithm: HashAlgorithm) -> io::Result<Digest> {
+    if size <= PARTIAL_HASH_SIZE * 2 {
+        return hash_file(path, algorithm).map(|(digest, _)| digest);
+    }
+    let mut file = File::open(path)?;
+    let mut buf = [0u8; PARTIAL_HASH_SIZE as usize];
+    let mut hasher = algorithm.hasher();
+    file.read_exact(&mut buf)?;
+    hasher.update(&buf);
+    file.seek(SeekFrom::End(-(PARTIAL_HASH_SIZE as i64)))?;
+    file.read_exact(&mut buf)?;
+    hasher.update(&buf);
+    Ok(hasher.digest())
+}
+
+/// compare two files byte by byte through fixed-size buffers,
+/// the final word before acting on files that only share a hash
+pub fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let (a, b) = (File::open(a)?, File::open(b)?);
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let mut a = BufReader::with_capacity(HASH_BUFFER_SIZE, a);
+    let mut b = BufReader::with_capacity(HASH_BUFFER_SIZE, b);
+    loop {
+        let (buf_a, buf_b) = (a.fill_buf()?, b.fill_buf()?);
+        let n = buf_a.len().min(buf_b.len());
+        if n == 0 {
+            return Ok(buf_a.is_empty() && buf_b.is_empty());
+        }
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+        a.consume(n);
+        b.consume(n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_hex_round_trip() {
+        let digest = Digest::from_hex("00ff7a").unwrap();
+        assert_eq!(digest.as_bytes(), &[0x00, 0xff, 0x7a]);
+        assert_eq!(digest.to_string(), "00ff7a");
+        assert_eq!(Digest::from_hex("00FF7A"), Some(digest));
+    }
+
+    #[test]
+    fn from_hex_rejects_invalid() {
+        assert_eq!(Digest::from_hex("0ff"), None);
+        assert_eq!(Digest::from_hex("zz"), None);
+        assert_eq!(Digest::from_hex("+f"), None);
+        assert_eq!(Digest::from_hex("é0"), None);
+        assert_eq!(Digest::from_hex(&"00".repeat(Digest::MAX_LEN + 1)), None);
+        assert!(Digest::from_hex(&"00".repeat(Digest::MAX_LEN)).is_some());
+    }
+
+    #[test]
+    fn partial_hash_boundary() {
+        let dir = std::env::temp_dir().join(format!("colek-partial-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let algorithm = HashAlgorithm::Sha256;
+        let contents: Vec<u8> = (0..PARTIAL_HASH_SIZE * 2 + 1).map(|x| x as u8).collect();
+
+        // up to twice the partial size the whole file is hashed
+        let whole = dir.join("whole");
+        let size = PARTIAL_HASH_SIZE * 2;
+        std::fs::write(&whole, &contents[..size as usize]).unwrap();
+        let (full, _) = hash_file(&whole, algorithm).unwrap();
+        assert_eq!(hash_file_partial(&whole, size, algorithm).unwrap(), full);
+
+        // one byte more and only the first and last blocks are
+        let split = dir.join("split");
+        let size = PARTIAL_HASH_SIZE * 2 + 1;
+        std::fs::write(&split, &contents).unwrap();
+        let block = PARTIAL_HASH_SIZE as usize;
+        let mut hasher = algorithm.hasher();
+        hasher.update(&contents[..block]);
+        hasher.update(&contents[contents.len() - block..]);
+        let partial = hash_file_partial(&split, size, algorithm).unwrap();
+        assert_eq!(partial, hasher.digest());
+        assert_ne!(partial, hash_file(&split, algorithm).unwrap().0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -9,6 +9,8 @@ pub enum ColekError {
     IoError(std::io::Error),
     Ignore(ignore::Error),
     Zip(zip::result::ZipError),
+    Json(serde_json::Error),
+    Csv(csv::Error),
 }
 
 impl std::error::Error for ColekError {}
@@ -21,6 +23,8 @@ impl Display for ColekError {
             ColekError::IoError(ioerr) => write!(f, "IO: {ioerr}"),
             ColekError::Ignore(err) => write!(f, "walkdir: {err}"),
             ColekError::Zip(err) => write!(f, "zip: {err}"),
+            ColekError::Json(err) => write!(f, "json: {err}"),
+            ColekError::Csv(err) => write!(f, "csv: {err}"),
         }
     }
 }
@@ -73,6 +77,18 @@ impl From<zip::result::ZipError> for ColekError {
     }
 }
 
+impl From<serde_json::Error> for ColekError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Json(value)
+    }
+}
+
+impl From<csv::Error> for ColekError {
+    fn from(value: csv::Error) -> Self {
+        Self::Csv(value)
+    }
+}
+
 #[macro_export]
 macro_rules! err_log {
     ($err:expr, $arg:literal) => {
@@ -1,6 +1,9 @@
 use std::{
     fmt::Display,
+    fs::File,
+    io::Read,
     ops::{BitOr, BitOrAssign},
+    path::Path,
 };
 
 #[repr(u8)]
@@ -85,47 +88,73 @@ impl Default for Filters {
     }
 }
 
-pub static MAGIC_BYTES: &[&[u8]] = &[
+pub static MAGIC_BYTES: &[(&[u8], &str)] = &[
     /*
      * MAGIC BYTES IMAGES
      */
-    b"\x89PNG\r\n\x1a\n",      /* Png */
-    &[0xff, 0xd8, 0xff],       /* Jpeg */
-    b"GIF89a",                 /* Gif */
-    b"GIF87a",                 /* Gif */
-    b"RIFF",                   /* WebP  */
-    b"MM\x00*",                /* Tiff */
-    b"II*\x00",                /* Tiff */
-    b"DDS ",                   /* Dds */
-    b"#?RADIANCE",             /* Hdr */
-    b"ftypheic",               /* Heic */
-    b"P1",                     /* Pnm */
-    b"P2",                     /* Pnm */
-    b"P3",                     /* Pnm */
-    b"P4",                     /* Pnm */
-    b"P5",                     /* Pnm */
-    b"P6",                     /* Pnm */
-    b"P7",                     /* Pnm */
-    b"farbfeld",               /* Farbfeld */
-    b"\0\0\0 ftypavif",        /* Avif */
-    b"\0\0\0\x1cftypavif",     /* Avif */
-    &[0x76, 0x2f, 0x31, 0x01], /* OpenExr */
-    b"qoif",                   /* Qoi */
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (&[0xff, 0xd8, 0xff], "jpeg"),
+    (b"GIF89a", "gif"),
+    (b"GIF87a", "gif"),
+    (b"RIFF", "webp"),
+    (b"MM\x00*", "tiff"),
+    (b"II*\x00", "tiff"),
+    (b"DDS ", "dds"),
+    (b"#?RADIANCE", "hdr"),
+    (b"ftypheic", "heic"),
+    (b"P1", "pnm"),
+    (b"P2", "pnm"),
+    (b"P3", "pnm"),
+    (b"P4", "pnm"),
+    (b"P5", "pnm"),
+    (b"P6", "pnm"),
+    (b"P7", "pnm"),
+    (b"farbfeld", "farbfeld"),
+    (b"\0\0\0 ftypavif", "avif"),
+    (b"\0\0\0\x1cftypavif", "avif"),
+    (&[0x76, 0x2f, 0x31, 0x01], "exr"),
+    (b"qoif", "qoi"),
     /*
      * MAGIC BYTES VIDEOS
      */
-    b"ftypisom",               /* ISO Base Media file (MPEG-4) */
-    b"ftypMSNV",               /* MPEG-4 */
-    &[0x00, 0x00, 0x01, 0xBA], /* MPEG */
-    &[0x00, 0x00, 0x01, 0xB3], /* MPEG */
-    &[0x1A, 0x45, 0xDF, 0xA3], /* Matroska(MKV), including WebM */
-    b"ftyp3g",                 /* 3gp */
-    b"FLV",                    /* Flash Video file */
+    (b"ftypisom", "mp4"),                /* ISO Base Media file (MPEG-4) */
+    (b"ftypMSNV", "mp4"),                /* MPEG-4 */
+    (&[0x00, 0x00, 0x01, 0xBA], "mpeg"), /* MPEG */
+    (&[0x00, 0x00, 0x01, 0xB3], "mpeg"), /* MPEG */
+    (&[0x1A, 0x45, 0xDF, 0xA3], "mkv"),  /* Matroska(MKV), including WebM */
+    (b"ftyp3g", "3gp"),                  /* 3gp */
+    (b"FLV", "flv"),                     /* Flash Video file */
 ];
 
 pub const MAGIC_BYTE_MAX_LEN: usize = 64;
 pub fn contains_magic_bytes(bytes: impl AsRef<[u8]>) -> bool {
-    MAGIC_BYTES.iter().any(|x| bytes.as_ref().starts_with(x))
+    magic_bytes_format(bytes).is_some()
+}
+
+pub fn magic_bytes_format(bytes: impl AsRef<[u8]>) -> Option<&'static str> {
+    let bytes = bytes.as_ref();
+    MAGIC_BYTES
+        .iter()
+        .find(|(magic, fmt)| {
+            // RIFF is also the container of wav and avi, only webp names itself after the size
+            bytes.starts_with(magic) && (*fmt != "webp" || bytes.get(8..12) == Some(b"WEBP"))
+        })
+        .map(|(_, fmt)| *fmt)
+}
+
+/// detect the format of a file, by its known extension or by its magic bytes
+pub fn detect_format(path: &Path) -> Option<String> {
+    let ext = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase());
+    if let Some(ext) = ext.filter(|ext| Filter::from_extension(ext).is_some()) {
+        return Some(ext);
+    }
+    let mut buf = [0u8; MAGIC_BYTE_MAX_LEN];
+    let mut file = File::open(path).ok()?;
+    let n = file.read(&mut buf[..]).ok()?;
+    magic_bytes_format(&buf[..n]).map(ToOwned::to_owned)
 }
 
 mod utils {
@@ -177,3 +206,19 @@ mod utils {
     }
     pub(super) use impl_filter;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn riff_is_webp_only_with_the_webp_tag() {
+        assert_eq!(
+            magic_bytes_format(b"RIFF\x24\x00\x00\x00WEBPVP8 "),
+            Some("webp")
+        );
+        assert_eq!(magic_bytes_format(b"RIFF\x24\x00\x00\x00WAVEfmt "), None);
+        assert_eq!(magic_bytes_format(b"RIFF\x24\x00\x00\x00AVI LIST"), None);
+        assert_eq!(magic_bytes_format(b"RIFF"), None);
+    }
+}
@@ -0,0 +1,590 @@
+//! Hashes of files that are already known, from a reference collection.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs::{File, Metadata},
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use clap::ValueEnum;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    cache::{cached, CacheKey, CacheStage, HashCache},
+    digest::{hash_file, Digest, HashAlgorithm},
+    manifest::ManifestFormat,
+};
+
+/// a hash list given on the command line, `PATH` or `ALGORITHM:PATH`
+/// when the algorithm can not be told from the list itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashList {
+    pub algorithm: Option<HashAlgorithm>,
+    pub path: PathBuf,
+}
+
+impl FromStr for HashList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let prefixed = s.split_once(':').and_then(|(algorithm, path)| {
+            let algorithm = ValueEnum::from_str(algorithm, true).ok()?;
+            Some((algorithm, path))
+        });
+        Ok(match prefixed {
+            Some((algorithm, path)) => Self {
+                algorithm: Some(algorithm),
+                path: path.into(),
+            },
+            None => Self {
+                algorithm: None,
+                path: s.into(),
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownHashes {
+    algorithm: HashAlgorithm,
+    digests: HashSet<Digest>,
+    /// sizes of the known files, `None` when the source does not record them
+    sizes: Option<HashSet<u64>>,
+}
+
+impl KnownHashes {
+    /// load the hashes from a directory (hashed now), a colek manifest,
+    /// a csv with a hash column (NSRL included) or a `sha256sum` style hash list,
+    /// a directory is hashed with `algorithm` unless the list names one
+    pub fn load(list: &HashList, algorithm: HashAlgorithm) -> crate::Result<Self> {
+        let path = &list.path;
+        let known = if path.is_dir() {
+            Self::from_dir(path, list.algorithm.unwrap_or(algorithm))?
+        } else {
+            let name = path.file_name().and_then(|x| x.to_str()).unwrap_or("");
+            let manifest = ManifestFormat::from_file_name(name).or_else(|| {
+                (path.extension().is_some_and(|x| x == "jsonl")).then_some(ManifestFormat::Jsonl)
+            });
+            match manifest {
+                Some(format) => Self::from_manifest(path, format, list.algorithm)?,
+                None if is_csv(path)? => Self::from_csv(path, list.algorithm)?,
+                None => Self::from_hash_list(path, list.algorithm)?,
+            }
+        };
+        log::info!(
+            "Loaded {} known {} hash(es) from '{}'",
+            known.digests.len(),
+            known.algorithm,
+            path.display()
+        );
+        Ok(known)
+    }
+
+    /// the hashes of `path`, of `algorithm` when it is known,
+    /// otherwise of the only algorithm with digests of their length
+    fn new(
+        path: &Path,
+        algorithm: Option<HashAlgorithm>,
+        digests: HashSet<Digest>,
+        sizes: Option<HashSet<u64>>,
+    ) -> crate::Result<Self> {
+        let lengths: BTreeSet<_> = digests.iter().map(|x| x.as_bytes().len()).collect();
+        let algorithm = match (algorithm, lengths.first()) {
+            _ if lengths.len() > 1 => {
+                return Err(
+                    format!("'{}' mixes digests of different lengths", path.display()).into(),
+                )
+            }
+            (Some(algorithm), Some(&len)) if len != algorithm.digest_len() => {
+                return Err(format!(
+                    "'{}' holds digests of {len} bytes, not {algorithm} ones",
+                    path.display()
+                )
+                .into())
+            }
+            (Some(algorithm), _) => algorithm,
+            (None, Some(&len)) => algorithm_of_len(path, len)?,
+            // nothing to compare, whatever the algorithm
+            (None, None) => HashAlgorithm::default(),
+        };
+        Ok(Self {
+            algorithm,
+            digests,
+            sizes,
+        })
+    }
+
+    fn from_dir(dir: &Path, algorithm: HashAlgorithm) -> crate::Result<Self> {
+        let files: Vec<_> = ignore::WalkBuilder::new(dir)
+            .standard_filters(false)
+            .build()
+            .filter_map(|entry| {
+                crate::err_log!(entry, "walking reference directory")
+                    .filter(|x| x.file_type().is_some_and(|x| x.is_file()))
+                    .map(ignore::DirEntry::into_path)
+            })
+            .collect();
+        log::info!("Hashing {} reference file(s)", files.len());
+        let hashed: Vec<_> = files
+            .into_par_iter()
+            .filter_map(|path| {
+                crate::err_log!(
+                    hash_file(&path, algorithm),
+                    "Failed to hash reference file '{}'",
+                    path.display()
+                )
+            })
+            .collect();
+        Ok(Self {
+            algorithm,
+            digests: hashed.iter().map(|(digest, _)| *digest).collect(),
+            sizes: Some(hashed.iter().map(|(_, size)| *size).collect()),
+        })
+    }
+
+    /// the algorithm recorded in the header, which the one of the command line must match
+    fn from_manifest(
+        path: &Path,
+        format: ManifestFormat,
+        algorithm: Option<HashAlgorithm>,
+    ) -> crate::Result<Self> {
+        let recorded = format.read_algorithm(File::open(path)?)?;
+        let algorithm = agreed(path, algorithm, recorded)?;
+        let entries = format.read_entries(File::open(path)?)?;
+        let mut digests = HashSet::with_capacity(entries.len());
+        for entry in &entries {
+            digests.insert(parse_hex(&entry.hash)?);
+        }
+        let sizes = entries.iter().map(|x| x.size).collect();
+        Self::new(path, algorithm, digests, Some(sizes))
+    }
+
+    /// one `<hex digest> <path>` per line, as written by `sha256sum` and friends
+    fn from_hash_list(path: &Path, algorithm: Option<HashAlgorithm>) -> crate::Result<Self> {
+        let mut digests = HashSet::new();
+        for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let hex = line.split_whitespace().next().unwrap_or_default();
+            let digest =
+                parse_hex(hex).map_err(|err| format!("{}:{}: {err}", path.display(), n + 1))?;
+            digests.insert(digest);
+        }
+        Self::new(path, algorithm, digests, None)
+    }
+
+    /// csv with a header, the hash is read from the column named after the algorithm
+    /// (`sha256`, `SHA-1`, `MD5`...), the first such column giving the algorithm when
+    /// none is named, or `hash`, and the size from `size` or `FileSize`
+    fn from_csv(path: &Path, algorithm: Option<HashAlgorithm>) -> crate::Result<Self> {
+        let recorded = ManifestFormat::Csv.read_algorithm(File::open(path)?)?;
+        let algorithm = agreed(path, algorithm, recorded)?;
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .from_reader(File::open(path)?);
+        let normalize = |x: &str| x.to_lowercase().replace(['-', '_'], "");
+        let headers: Vec<_> = reader.headers()?.iter().map(normalize).collect();
+        let column = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| headers.iter().position(|x| x == name))
+        };
+        let named = headers.iter().enumerate().find_map(|(i, header)| {
+            let algorithm = HashAlgorithm::value_variants()
+                .iter()
+                .find(|x| x.to_string() == *header)?;
+            Some((i, *algorithm))
+        });
+        let (hash, algorithm) = match algorithm {
+            Some(algorithm) => (
+                column(&[&algorithm.to_string(), "hash", "digest"]),
+                Some(algorithm),
+            ),
+            None => match named {
+                Some((hash, algorithm)) => (Some(hash), Some(algorithm)),
+                None => (column(&["hash", "digest"]), None),
+            },
+        };
+        let Some(hash) = hash else {
+            let name = algorithm.map_or_else(|| "hash".to_owned(), |x| x.to_string());
+            return Err(format!("'{}' has no {name} column", path.display()).into());
+        };
+        let size = column(&["size", "filesize"]);
+        let mut digests = HashSet::new();
+        let mut sizes = size.map(|_| HashSet::new());
+        for (n, record) in reader.records().enumerate() {
+            let record = record?;
+            let line = || format!("{}:{}", path.display(), n + 2);
+            let digest = parse_hex(record.get(hash).unwrap_or_default())
+                .map_err(|err| format!("{}: {err}", line()))?;
+            digests.insert(digest);
+            if let (Some(sizes), Some(size)) = (sizes.as_mut(), size) {
+                let size = record.get(size).unwrap_or_default();
+                let size = size
+                    .parse()
+                    .map_err(|_| format!("{}: `{size}` is not a size", line()))?;
+                sizes.insert(size);
+            }
+        }
+        Self::new(path, algorithm, digests, sizes)
+    }
+
+    #[inline]
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// every hash of both, sizes are only kept when both know them
+    pub fn extend(&mut self, other: Self) {
+        self.digests.extend(other.digests);
+        self.sizes = match (self.sizes.take(), other.sizes) {
+            (Some(mut a), Some(b)) => {
+                a.extend(b);
+                Some(a)
+            }
+            _ => None,
+        };
+    }
+
+    #[inline]
+    pub fn contains(&self, digest: &Digest) -> bool {
+        self.digests.contains(digest)
+    }
+
+    /// `false` only when no known file has this size, so the file does not need to be hashed
+    #[inline]
+    pub fn may_contain_size(&self, size: u64) -> bool {
+        self.sizes.as_ref().is_none_or(|x| x.contains(&size))
+    }
+}
+
+/// a csv file when its extension say so, or when its first line is not a hash
+fn is_csv(path: &Path) -> crate::Result<bool> {
+    if path
+        .extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("csv"))
+    {
+        return Ok(true);
+    }
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let first = line.split_whitespace().next().unwrap_or_default();
+        return Ok(Digest::from_hex(first).is_none());
+    }
+    Ok(false)
+}
+
+fn parse_hex(hex: &str) -> crate::Result<Digest> {
+    Digest::from_hex(hex).ok_or_else(|| format!("`{hex}` is not a hex digest").into())
+}
+
+/// the algorithm named on the command line or recorded in the list, which must not differ
+fn agreed(
+    path: &Path,
+    named: Option<HashAlgorithm>,
+    recorded: Option<HashAlgorithm>,
+) -> crate::Result<Option<HashAlgorithm>> {
+    match (named, recorded) {
+        (Some(named), Some(recorded)) if named != recorded => Err(format!(
+            "'{}' is hashed with {recorded}, not {named}",
+            path.display()
+        )
+        .into()),
+        _ => Ok(named.or(recorded)),
+    }
+}
+
+/// the only algorithm with digests of `len` bytes, md5 and xxh3 or sha256 and blake3
+/// can not be told apart and must be named
+fn algorithm_of_len(path: &Path, len: usize) -> crate::Result<HashAlgorithm> {
+    let candidates: Vec<_> = HashAlgorithm::value_variants()
+        .iter()
+        .filter(|x| x.digest_len() == len)
+        .collect();
+    match candidates[..] {
+        [algorithm] => Ok(*algorithm),
+        [] => Err(format!(
+            "'{}' holds digests of {len} bytes, of no known algorithm",
+            path.display()
+        )
+        .into()),
+        [first, ..] => {
+            let names: Vec<_> = candidates.iter().map(ToString::to_string).collect();
+            Err(format!(
+                "'{path}' holds digests of {len} bytes, which may be {}, name the algorithm as `{first}:{path}`",
+                names.join(" or "),
+                path = path.display(),
+            )
+            .into())
+        }
+    }
+}
+
+/// hash lists deciding which scanned files are collected
+#[derive(Debug, Clone)]
+pub struct KnownFilter {
+    /// only files in one of them are collected, one list per algorithm
+    allow: Vec<KnownHashes>,
+    /// files in one of them are never collected, one list per algorithm
+    block: Vec<KnownHashes>,
+    /// number of files hashed concurrently
+    jobs: usize,
+    cache: Option<Arc<HashCache>>,
+}
+
+impl KnownFilter {
+    /// `None` when no list is given, so no file needs to be hashed while scanning,
+    /// directories are hashed with `algorithm` unless the list names one
+    pub fn load(
+        allowlists: &[HashList],
+        blocklists: &[HashList],
+        algorithm: HashAlgorithm,
+    ) -> crate::Result<Option<Self>> {
+        let load = |lists: &[HashList]| -> crate::Result<Vec<KnownHashes>> {
+            let mut known: Vec<KnownHashes> = Vec::new();
+            for list in lists {
+                let list = KnownHashes::load(list, algorithm)?;
+                match known.iter_mut().find(|x| x.algorithm == list.algorithm) {
+                    Some(known) => known.extend(list),
+                    None => known.push(list),
+                }
+            }
+            Ok(known)
+        };
+        let (allow, block) = (load(allowlists)?, load(blocklists)?);
+        if allow.is_empty() && block.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            allow,
+            block,
+            jobs: 1,
+            cache: None,
+        }))
+    }
+
+    /// hash `jobs` files at once, reusing and remembering the digests of `cache`
+    pub fn with_hashing(self, jobs: usize, cache: Option<Arc<HashCache>>) -> Self {
+        Self {
+            jobs: jobs.max(1),
+            cache,
+            ..self
+        }
+    }
+
+    #[inline]
+    pub fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    /// whether the file at `path` pass every list, hashed once for each algorithm
+    /// of the lists that may contain its size
+    pub fn accepts(&self, path: &Path, metadata: Option<&Metadata>) -> bool {
+        let size = metadata.map_or(0, Metadata::len);
+        let allow: Vec<_> = self
+            .allow
+            .iter()
+            .filter(|x| x.may_contain_size(size))
+            .collect();
+        let block: Vec<_> = self
+            .block
+            .iter()
+            .filter(|x| x.may_contain_size(size))
+            .collect();
+        if !self.allow.is_empty() && allow.is_empty() {
+            return false;
+        }
+        let mut hashes: Vec<(HashAlgorithm, Digest)> = Vec::new();
+        let mut contains = |list: &KnownHashes| -> io::Result<bool> {
+            let hash = match hashes.iter().find(|(x, _)| *x == list.algorithm) {
+                Some((_, hash)) => *hash,
+                None => {
+                    let key = metadata.and_then(|x| CacheKey::new(x, list.algorithm));
+                    let hash = cached(self.cache.as_deref(), key, path, CacheStage::Full, || {
+                        hash_file(path, list.algorithm).map(|(hash, _)| hash)
+                    })?;
+                    hashes.push((list.algorithm, hash));
+                    hash
+                }
+            };
+            Ok(list.contains(&hash))
+        };
+        let mut passes = || -> io::Result<bool> {
+            let mut allowed = allow.is_empty();
+            for list in &allow {
+                allowed = allowed || contains(list)?;
+            }
+            if !allowed {
+                return Ok(false);
+            }
+            for list in &block {
+                if contains(list)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        };
+        passes().unwrap_or_else(|err| {
+            log::error!("Failed to hash '{}' - {err}", path.display());
+            false
+        })
+    }
+
+    /// write back the digests computed while filtering
+    pub fn save_cache(&self) {
+        if let Some(ref cache) = self.cache {
+            crate::err_log!(cache.save(), "Failed to save the hash cache");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const SHA1_ABC: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+    const MD5_ABC: &str = "900150983cd24fb0d6963f7d28e17f72";
+
+    /// a file holding `contents` in a directory of its own test
+    fn write(test: &str, name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("colek-known-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn clean(path: &Path) {
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    fn load(list: &str) -> crate::Result<KnownHashes> {
+        KnownHashes::load(&list.parse().unwrap(), HashAlgorithm::Xxh3)
+    }
+
+    fn digest(hex: &str) -> Digest {
+        Digest::from_hex(hex).unwrap()
+    }
+
+    #[test]
+    fn hash_list_with_algorithm_prefix() {
+        let list: HashList = "MD5:/tmp/list.txt".parse().unwrap();
+        assert_eq!(list.algorithm, Some(HashAlgorithm::Md5));
+        assert_eq!(list.path, PathBuf::from("/tmp/list.txt"));
+        // a prefix naming no algorithm is part of the path
+        let list: HashList = "c:/list.txt".parse().unwrap();
+        assert_eq!(list.algorithm, None);
+        assert_eq!(list.path, PathBuf::from("c:/list.txt"));
+    }
+
+    #[test]
+    fn sha_sum_lists() {
+        let path = write(
+            "sum",
+            "list.sha1",
+            &format!("# sha1sum output\n\n{SHA1_ABC} *abc.png\n"),
+        );
+        let known = load(path.to_str().unwrap()).unwrap();
+        assert_eq!(known.algorithm(), HashAlgorithm::Sha1);
+        assert!(known.contains(&digest(SHA1_ABC)));
+        // a hash list does not record sizes
+        assert!(known.may_contain_size(12345));
+
+        // sha256 and blake3 digests are of the same length
+        let path = write("sum", "list.sha256", &format!("{SHA256_ABC}  abc.png\n"));
+        assert!(load(path.to_str().unwrap()).is_err());
+        let known = load(&format!("sha256:{}", path.display())).unwrap();
+        assert_eq!(known.algorithm(), HashAlgorithm::Sha256);
+        assert!(known.contains(&digest(SHA256_ABC)));
+        clean(&path);
+    }
+
+    #[test]
+    fn ambiguous_digest_length_must_be_named() {
+        let path = write("ambiguous", "list.md5", &format!("{MD5_ABC}  abc.png\n"));
+        let err = load(path.to_str().unwrap()).unwrap_err().to_string();
+        assert!(err.contains("xxh3 or md5"), "{err}");
+        let known = load(&format!("md5:{}", path.display())).unwrap();
+        assert_eq!(known.algorithm(), HashAlgorithm::Md5);
+        assert!(known.contains(&digest(MD5_ABC)));
+        // named as another algorithm of another length
+        assert!(load(&format!("sha1:{}", path.display())).is_err());
+        clean(&path);
+    }
+
+    #[test]
+    fn nsrl_header_selects_the_sha1_column() {
+        let path = write(
+            "nsrl",
+            "NSRLFile.txt",
+            &format!(
+                "\"SHA-1\",\"MD5\",\"CRC32\",\"FileName\",\"FileSize\",\"ProductCode\"\n\
+                 \"{}\",\"{}\",\"352441C2\",\"abc.png\",3,1\n",
+                SHA1_ABC.to_uppercase(),
+                MD5_ABC.to_uppercase(),
+            ),
+        );
+        assert!(is_csv(&path).unwrap());
+        let known = load(path.to_str().unwrap()).unwrap();
+        assert_eq!(known.algorithm(), HashAlgorithm::Sha1);
+        assert!(known.contains(&digest(SHA1_ABC)));
+        assert!(known.may_contain_size(3));
+        assert!(!known.may_contain_size(4));
+
+        // the named algorithm picks its own column
+        let known = load(&format!("md5:{}", path.display())).unwrap();
+        assert_eq!(known.algorithm(), HashAlgorithm::Md5);
+        assert!(known.contains(&digest(MD5_ABC)));
+        clean(&path);
+    }
+
+    #[test]
+    fn manifest_algorithm_must_match_the_named_one() {
+        let path = write(
+            "manifest",
+            "manifest.csv",
+            &format!(
+                "# algorithm: sha256\n\
+                 source,drive_name,drive_type,size,mtime,format,hash,dest,deduplicated\n\
+                 /abc.png,disk,,3,,png,{SHA256_ABC},abc.png,false\n"
+            ),
+        );
+        let known = load(path.to_str().unwrap()).unwrap();
+        assert_eq!(known.algorithm(), HashAlgorithm::Sha256);
+        assert!(known.contains(&digest(SHA256_ABC)));
+        assert!(known.may_contain_size(3));
+        assert!(!known.may_contain_size(4));
+
+        let err = load(&format!("sha1:{}", path.display()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("is hashed with sha256, not sha1"), "{err}");
+        clean(&path);
+    }
+
+    #[test]
+    fn csv_by_extension_or_first_line() {
+        let header = write("is-csv", "list.txt", "# comment\nhash,size\n");
+        assert!(is_csv(&header).unwrap());
+        let list = write(
+            "is-csv",
+            "sums.txt",
+            &format!("# comment\n{MD5_ABC}  abc\n"),
+        );
+        assert!(!is_csv(&list).unwrap());
+        let named = write("is-csv", "sums.CSV", &format!("{MD5_ABC}\n"));
+        assert!(is_csv(&named).unwrap());
+        clean(&named);
+    }
+}
@@ -1,31 +1,50 @@
 mod app;
+mod cache;
+mod digest;
 mod error;
 mod filters;
+mod known;
 mod logger;
+mod manifest;
+mod mounts;
 mod system;
+mod trash;
 
 #[allow(unused)]
 use log::{debug, error, info, warn};
 
 use app::App;
+use digest::HashAlgorithm;
 use error::{ColekError, Result};
+use known::{HashList, KnownFilter};
 use logger::LogLevel;
-use std::{path::PathBuf, process::ExitCode};
+use manifest::{Manifest, ManifestFormat};
+use std::{path::PathBuf, process::ExitCode, sync::Arc};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use filters::{Filter, Filters};
 
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 fn main() -> ExitCode {
     let args = CliArgs::parse();
+    if let Err(err) = args.validate() {
+        err.exit();
+    }
     logger::init(args.verbose);
     log::info!("{APP_NAME} - Starting Program");
 
-    let mut sys = system::SystemDiskInfo::new();
+    let mut sys = system::SystemDiskInfo::new(&args.include_mounts);
     let filter = args.filter.unwrap_or_else(|| vec![Filter::Image]);
     let filter = Filters::from(filter);
+    let known = match KnownFilter::load(&args.allowlist, &args.blocklist, args.algorithm) {
+        Ok(known) => known,
+        Err(err) => {
+            log::error!("{APP_NAME} - Failed to load the hash lists: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
 
-    if let Err(err) = args.command.run(&mut sys, filter) {
+    if let Err(err) = args.command.run(&mut sys, filter, known, args.algorithm) {
         log::error!("{APP_NAME} - Failed on running command: {err}");
         ExitCode::FAILURE
     } else {
@@ -40,24 +59,97 @@ pub struct CliArgs {
     #[clap(long, short, value_delimiter=',', action=clap::ArgAction::Append)]
     filter: Option<Vec<Filter>>,
 
+    /// hash algorithm used for duplicates and manifests
+    #[arg(long, short, default_value = "xxh3")]
+    algorithm: HashAlgorithm,
+
+    /// only collect the files whose hash is in this list: a `sha256sum` style
+    /// hash list, a csv with a hash column (NSRL included) or a manifest, the algorithm
+    /// is read from the manifest header or the csv column, or given as `md5:PATH`
+    #[arg(long, action = clap::ArgAction::Append)]
+    allowlist: Vec<HashList>,
+
+    /// never collect the files whose hash is in this list, same formats as `--allowlist`
+    #[arg(long, action = clap::ArgAction::Append)]
+    blocklist: Vec<HashList>,
+
+    /// also scan the mounts of these kinds, only disks are scanned by default
+    #[arg(long, value_delimiter = ',', action = clap::ArgAction::Append)]
+    include_mounts: Vec<mounts::MountKind>,
+
     /// set max verbosity level for stdout/stderr logger
-    #[arg(long, short, default_value = "warn")]
+    #[arg(long, short, default_value = "warn", ignore_case = true)]
     verbose: LogLevel,
 
     #[command(subcommand)]
     command: Commands,
 }
 
+impl CliArgs {
+    /// checks between options that clap can not express
+    fn validate(&self) -> std::result::Result<(), clap::Error> {
+        if let Commands::Hash(ref hash) = self.command {
+            if hash.trash && hash.duplicate != app::HasherEventDuplicate::Remove {
+                return Err(CliArgs::command().error(
+                    clap::error::ErrorKind::ArgumentConflict,
+                    "`--trash` can only be used with `--duplicate remove`",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, clap::Subcommand)]
 enum Commands {
     /// Output the scaned file to Stdout ( the path name )
-    Stdout,
+    Stdout {
+        /// output format, plain and null write one file per line or per NUL byte
+        #[arg(long, default_value = "plain")]
+        format: app::StdoutFormat,
+
+        /// fields written for each file, only the path for plain and null
+        /// and every field for jsonl and csv by default
+        #[arg(long, value_delimiter = ',')]
+        fields: Option<Vec<app::StdoutField>>,
+
+        /// print the count and size of the scanned files to stderr when finished
+        #[arg(long)]
+        summary: bool,
+
+        /// report counts and bytes per category, extension, drive, top-level directory,
+        /// size and year, with the largest files, to stderr unless `--stats-output` is given
+        #[arg(long)]
+        stats: Option<app::StatsFormat>,
+
+        /// write the statistics report into this file
+        #[arg(long, requires = "stats")]
+        stats_output: Option<PathBuf>,
+
+        /// number of largest files listed by the statistics report
+        #[arg(long, default_value_t = 10, requires = "stats")]
+        top: usize,
+    },
 
     /// Just Copy in the target Directories
     Copy {
         /// target directories to copy the files scanned
         #[arg(long, short, required = false)]
         target: Option<PathBuf>,
+
+        /// format of the manifest written at the root of target directories
+        #[arg(long, short, default_value = "jsonl")]
+        manifest: ManifestFormat,
+
+        /// reference collection, a directory, a manifest or a `sha256sum` style hash list,
+        /// given as `md5:PATH` when the algorithm can not be told, scanned files already
+        /// in it are not copied
+        #[arg(long)]
+        against: Option<HashList>,
+
+        /// copy each content only once, the manifest still lists every source
+        #[arg(long)]
+        dedupe: bool,
     },
 
     /// Output to Zip Files
@@ -65,37 +157,240 @@ enum Commands {
         /// output files
         #[arg(long, short, required = false)]
         output: Option<PathBuf>,
+
+        /// format of the manifest embedded inside the zip archive
+        #[arg(long, short, default_value = "jsonl")]
+        manifest: ManifestFormat,
+
+        /// append only new or changed files into an existing archive instead of truncating it
+        #[arg(long, short)]
+        update: bool,
+
+        /// encrypt the entries with WinZip AES-256, the password is prompted
+        /// unless `--password-env` or `--password-file` is given
+        #[arg(long, short)]
+        encrypt: bool,
+
+        /// read the encryption password from this environment variable
+        #[arg(long, requires = "encrypt", conflicts_with = "password_file")]
+        password_env: Option<String>,
+
+        /// read the encryption password from the first line of this file
+        #[arg(long, requires = "encrypt")]
+        password_file: Option<PathBuf>,
+
+        /// sort entries and normalise their metadata, so the same files always
+        /// produce a byte-identical archive
+        #[arg(long, conflicts_with = "encrypt")]
+        deterministic: bool,
+
+        /// store each content only once, the manifest still lists every source
+        #[arg(long)]
+        dedupe: bool,
+    },
+
+    /// Find duplicates of the file scanned by hashing their contents
+    Hash(app::HashArgs),
+
+    /// Put back the duplicates moved into a quarantine directory
+    Restore {
+        /// quarantine directory given to `hash --quarantine`
+        quarantine: PathBuf,
+    },
+
+    /// Maintain the hashes kept between `hash` runs
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// List every drive, how it is classified and whether it is scanned
+    Drives {
+        /// print the drives as json
+        #[arg(long)]
+        json: bool,
+
+        /// also list the mounts excluded from scanning, memory and kernel filesystems included
+        #[arg(long)]
+        all: bool,
     },
 
-    /// Hash the file scanned using sha256
-    Hash {
-        /// on duplicate event
-        #[arg(short, long, default_value = "print")]
-        duplicate: app::HasherEventDuplicate,
+    /// Collect into a content-addressed store, shared by every machine collected
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Store {
+        /// store directory, created when missing
+        #[arg(required = true)]
+        dir: Option<PathBuf>,
+
+        #[command(subcommand)]
+        command: Option<StoreCommand>,
     },
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, clap::Subcommand)]
+enum StoreCommand {
+    /// Lay the stored files out as a normal directory tree
+    Checkout {
+        /// store directory
+        store: PathBuf,
+
+        /// directory receiving the files
+        dest: PathBuf,
+
+        /// path of each file inside `dest`, with the placeholders {host}, {drive},
+        /// {path}, {name}, {stem}, {ext}, {hash}, {year}, {month} and {day}
+        #[arg(long, short, default_value = "{host}/{path}")]
+        template: String,
+
+        /// hard link the files to the objects instead of copying them,
+        /// they are then read-only as the objects are
+        #[arg(long)]
+        hardlink: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::Subcommand)]
+enum CacheCommand {
+    /// Drop the hashes of files that were deleted or changed
+    Prune,
+    /// Show the number of cached hashes
+    Stats,
+}
+
+impl CacheCommand {
+    pub fn run(self) -> Result<()> {
+        let cache = cache::HashCache::open(cache::HashCache::default_path()?)?;
+        match self {
+            CacheCommand::Prune => {
+                let removed = cache.prune();
+                cache.save()?;
+                println!("pruned {removed} stale hash(es)");
+            }
+            CacheCommand::Stats => cache.print_stats(),
+        }
+        Ok(())
+    }
+}
+
+/// the hashes kept between runs, hashing without them when they can not be read
+fn open_cache() -> Option<Arc<cache::HashCache>> {
+    let cache = cache::HashCache::default_path().and_then(cache::HashCache::open);
+    err_log!(cache, "Failed to open the hash cache, hashing without it").map(Arc::new)
+}
+
 impl Commands {
-    pub fn run(self, sys: &mut system::SystemDiskInfo, filter: Filters) -> Result<()> {
+    pub fn run(
+        self,
+        sys: &mut system::SystemDiskInfo,
+        filter: Filters,
+        known: Option<KnownFilter>,
+        algorithm: HashAlgorithm,
+    ) -> Result<()> {
+        match self {
+            Commands::Restore { quarantine } => return app::restore(&quarantine),
+            Commands::Cache { command } => return command.run(),
+            Commands::Drives { json, all } => return sys.print_drives(json, all),
+            Commands::Store {
+                command:
+                    Some(StoreCommand::Checkout {
+                        store,
+                        dest,
+                        template,
+                        hardlink,
+                    }),
+                ..
+            } => return app::checkout(&store, &dest, &template, hardlink),
+            _ => {}
+        }
         let Some(drives) = sys.generic_drive() else {
             return Err(crate::ColekError::NoGenericDrive);
         };
+        // one cache shared by the hash lists and `hash`, so neither overwrites the other
+        let (jobs, use_cache) = match self {
+            Commands::Hash(ref args) => (args.jobs(), !args.no_cache),
+            _ => (
+                std::thread::available_parallelism().map_or(1, |x| x.get()),
+                true,
+            ),
+        };
+        let cache = match self {
+            _ if !use_cache => None,
+            Commands::Hash(ref args) if args.similar.is_none() => open_cache(),
+            _ if known.is_some() => open_cache(),
+            _ => None,
+        };
+        let known = known.map(|x| Arc::new(x.with_hashing(jobs, cache.clone())));
         match self {
-            Commands::Stdout => {
-                let mut application = app::AppDefault::new()?;
-                application.run(drives, filter)
+            Commands::Stdout {
+                format,
+                fields,
+                summary,
+                stats,
+                stats_output,
+                top,
+            } => {
+                let stats = stats.map(|format| (format, stats_output, top));
+                let mut application =
+                    app::AppDefault::new(format, fields, summary, stats, sys.drives.clone())?;
+                application.run(drives, filter, known)
+            }
+            Commands::Copy {
+                target,
+                manifest,
+                against,
+                dedupe,
+            } => {
+                let against = against
+                    .map(|list| known::KnownHashes::load(&list, algorithm))
+                    .transpose()?;
+                let manifest = Manifest::new(manifest, sys, algorithm);
+                let mut application =
+                    app::AppCopy::new(sys.dest(target), manifest, against, dedupe)?;
+                application.run(drives, filter, known)
+            }
+            Commands::Zip {
+                output,
+                manifest,
+                update,
+                encrypt,
+                password_env,
+                password_file,
+                deterministic,
+                dedupe,
+            } => {
+                let password = match (password_env, password_file) {
+                    _ if !encrypt => None,
+                    (Some(var), _) => Some(app::ZipPassword::Env(var).read()?),
+                    (_, Some(path)) => Some(app::ZipPassword::File(path).read()?),
+                    (None, None) => Some(app::ZipPassword::Prompt.read()?),
+                };
+                let manifest = Manifest::new(manifest, sys, algorithm);
+                let output = sys.dest_file(output, "zip");
+                let mut application =
+                    app::AppZip::new(output, manifest, update, password, deterministic, dedupe)?;
+                application.run(drives, filter, known)
             }
-            Commands::Copy { target } => {
-                let mut application = app::AppCopy::new(sys.dest(target))?;
-                application.run(drives, filter)
+            Commands::Hash(args) => {
+                if let Some(kind) = args.similar {
+                    let mut application = app::AppSimilar::new(args, kind)?;
+                    return application.run(drives, filter, known);
+                }
+                let sources = drives.iter().map(|x| x.path.clone()).collect();
+                let mut application = app::AppHasher::new(args, algorithm, sources, cache)?;
+                application.run(drives, filter, known)
             }
-            Commands::Zip { output } => {
-                let mut application = app::AppZip::new(sys.dest(output))?;
-                application.run(drives, filter)
+            Commands::Store {
+                dir: Some(dir),
+                command: None,
+            } => {
+                let mut application = app::AppStore::new(dir, sys, algorithm)?;
+                application.run(drives, filter, known)
             }
-            Commands::Hash { duplicate } => {
-                let mut application = app::AppHasher::new(duplicate);
-                application.run(drives, filter)
+            Commands::Restore { .. }
+            | Commands::Cache { .. }
+            | Commands::Drives { .. }
+            | Commands::Store { .. } => {
+                unreachable!("handled before scanning")
             }
         }
     }
@@ -0,0 +1,279 @@
+use std::{
+    fs::Metadata,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    digest::HashAlgorithm,
+    system::{DiskPartition, DriveType, SystemDiskInfo},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ManifestFormat {
+    #[default]
+    Jsonl,
+    Csv,
+}
+
+impl ManifestFormat {
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Csv => "csv",
+        }
+    }
+
+    /// `manifest.<ext>` for the first one, `manifest.<index>.<ext>` for the next ones
+    pub fn file_name(self, index: usize) -> String {
+        match index {
+            0 => format!("{MANIFEST_STEM}.{}", self.extension()),
+            n => format!("{MANIFEST_STEM}.{n}.{}", self.extension()),
+        }
+    }
+
+    /// format of the file if `name` looks like a manifest written by [`ManifestFormat::file_name`]
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let rest = name.strip_prefix(MANIFEST_STEM)?.strip_prefix('.')?;
+        let (index, ext) = rest.rsplit_once('.').unwrap_or(("", rest));
+        if !index.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        match ext {
+            "jsonl" => Some(Self::Jsonl),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+
+    /// read back all the entries of a manifest, the header is skipped
+    pub fn read_entries(self, reader: impl Read) -> crate::Result<Vec<ManifestEntry>> {
+        match self {
+            Self::Jsonl => {
+                let mut entries = Vec::new();
+                for line in BufReader::new(reader).lines() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    if let ManifestRecord::Entry(entry) = serde_json::from_str(&line)? {
+                        entries.push(entry);
+                    }
+                }
+                Ok(entries)
+            }
+            Self::Csv => csv::ReaderBuilder::new()
+                .comment(Some(b'#'))
+                .from_reader(reader)
+                .deserialize()
+                .map(|x| x.map_err(From::from))
+                .collect(),
+        }
+    }
+
+    /// hash algorithm recorded in the header of a manifest
+    pub fn read_algorithm(self, reader: impl Read) -> crate::Result<Option<HashAlgorithm>> {
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            match self {
+                Self::Jsonl if line.trim().is_empty() => continue,
+                Self::Jsonl => {
+                    return match serde_json::from_str(&line)? {
+                        ManifestRecord::Header(header) => Ok(Some(header.algorithm)),
+                        ManifestRecord::Entry(_) => Ok(None),
+                    }
+                }
+                Self::Csv => match line.strip_prefix("# algorithm: ") {
+                    Some(algorithm) => {
+                        let algorithm = clap::ValueEnum::from_str(algorithm.trim(), true)?;
+                        return Ok(Some(algorithm));
+                    }
+                    None if line.starts_with('#') => continue,
+                    None => return Ok(None),
+                },
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub const MANIFEST_STEM: &str = "manifest";
+
+/// modified time of a file in seconds since unix epoch
+pub fn mtime_secs(metadata: &Metadata) -> Option<u64> {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+        .map(|x| x.as_secs())
+}
+
+/// date of the unix `days`, as (year, month, day)
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// information about the machine and the invocation that produce the output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestHeader {
+    pub version: String,
+    pub algorithm: HashAlgorithm,
+    pub args: Vec<String>,
+    pub name: Option<String>,
+    pub host_name: Option<String>,
+    pub os_version: Option<String>,
+    pub kernel_version: Option<String>,
+}
+
+impl ManifestHeader {
+    pub fn new(sys: &SystemDiskInfo, algorithm: HashAlgorithm) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            algorithm,
+            args: std::env::args().collect(),
+            name: sys.name.clone(),
+            host_name: sys.host_name.clone(),
+            os_version: sys.os_version.clone(),
+            kernel_version: sys.kernel_version.clone(),
+        }
+    }
+}
+
+/// single collected file, where it came from and where it goes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub source: PathBuf,
+    pub drive_name: String,
+    pub drive_type: Option<DriveType>,
+    pub size: u64,
+    pub mtime: Option<u64>,
+    pub format: Option<String>,
+    pub hash: String,
+    pub dest: String,
+    /// contents already stored for another source, `dest` is that stored copy
+    #[serde(default)]
+    pub deduplicated: bool,
+}
+
+impl ManifestEntry {
+    pub fn new(source: &Path, metadata: &Metadata, hash: String, dest: impl Into<String>) -> Self {
+        Self {
+            source: source.to_path_buf(),
+            drive_name: String::new(),
+            drive_type: None,
+            size: metadata.len(),
+            mtime: mtime_secs(metadata),
+            format: crate::filters::detect_format(source),
+            hash,
+            dest: dest.into(),
+            deduplicated: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ManifestRecord {
+    Header(ManifestHeader),
+    Entry(ManifestEntry),
+}
+
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    format: ManifestFormat,
+    header: ManifestHeader,
+    drives: Arc<[DiskPartition]>,
+    entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new(format: ManifestFormat, sys: &SystemDiskInfo, algorithm: HashAlgorithm) -> Self {
+        Self {
+            format,
+            header: ManifestHeader::new(sys, algorithm),
+            drives: sys.drives.clone().into(),
+            entries: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub const fn format(&self) -> ManifestFormat {
+        self.format
+    }
+
+    /// algorithm used for the `hash` of every entry
+    #[inline]
+    pub const fn algorithm(&self) -> HashAlgorithm {
+        self.header.algorithm
+    }
+
+    /// record the entry, filling the drive the source file lives on
+    pub fn push(&mut self, mut entry: ManifestEntry) {
+        if let Some(drive) = DiskPartition::find(&self.drives, &entry.source) {
+            entry.drive_name = drive.name.clone();
+            entry.drive_type = drive.tp;
+        }
+        self.entries.push(entry);
+    }
+
+    pub fn write_to(&self, writer: impl Write) -> crate::Result<()> {
+        match self.format {
+            ManifestFormat::Jsonl => self.write_jsonl(writer),
+            ManifestFormat::Csv => self.write_csv(writer),
+        }
+    }
+
+    fn write_jsonl(&self, mut writer: impl Write) -> crate::Result<()> {
+        let header = ManifestRecord::Header(self.header.clone());
+        serde_json::to_writer(&mut writer, &header)?;
+        writeln!(writer)?;
+        for entry in &self.entries {
+            serde_json::to_writer(&mut writer, &ManifestRecord::Entry(entry.clone()))?;
+            writeln!(writer)?;
+        }
+        writer.flush().map_err(From::from)
+    }
+
+    /// the header block is written as `#` comment lines before the csv table
+    fn write_csv(&self, mut writer: impl Write) -> crate::Result<()> {
+        let h = &self.header;
+        let none = || "-".to_owned();
+        writeln!(writer, "# version: {}", h.version)?;
+        writeln!(writer, "# algorithm: {}", h.algorithm)?;
+        writeln!(writer, "# args: {}", h.args.join(" "))?;
+        writeln!(writer, "# name: {}", h.name.clone().unwrap_or_else(none))?;
+        writeln!(
+            writer,
+            "# host_name: {}",
+            h.host_name.clone().unwrap_or_else(none)
+        )?;
+        writeln!(
+            writer,
+            "# os_version: {}",
+            h.os_version.clone().unwrap_or_else(none)
+        )?;
+        writeln!(
+            writer,
+            "# kernel_version: {}",
+            h.kernel_version.clone().unwrap_or_else(none)
+        )?;
+        let mut csv = csv::Writer::from_writer(writer);
+        for entry in &self.entries {
+            csv.serialize(entry)?;
+        }
+        csv.flush().map_err(From::from)
+    }
+}
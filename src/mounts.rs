@@ -0,0 +1,360 @@
+//! Mounted filesystems, classified by their type instead of their mount point.
+
+use serde::{Deserialize, Serialize};
+
+/// what a mounted filesystem is, only disks are scanned unless opted in with `--include-mounts`
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum MountKind {
+    /// a filesystem stored on a block device
+    #[value(skip)]
+    Disk,
+    /// tmpfs and ramfs, held in memory
+    Memory,
+    /// kernel filesystems such as proc, sysfs or cgroup
+    Pseudo,
+    /// overlay and aufs, as used by containers
+    Overlay,
+    /// squashfs, iso and loop mounted images, snaps included
+    Image,
+    /// nfs, cifs, sshfs and the other network filesystems
+    Network,
+    /// a directory of a filesystem already mounted elsewhere
+    Bind,
+}
+
+impl MountKind {
+    /// the kind of a mount of filesystem `fs_type` from `source`
+    pub fn classify(fs_type: &str, source: &str) -> Self {
+        let fs_type = fs_type.to_lowercase();
+        match fs_type.as_str() {
+            "tmpfs" | "ramfs" => Self::Memory,
+            "proc" | "sysfs" | "devtmpfs" | "devpts" | "cgroup" | "cgroup2" | "securityfs"
+            | "debugfs" | "tracefs" | "pstore" | "bpf" | "configfs" | "mqueue" | "hugetlbfs"
+            | "autofs" | "binfmt_misc" | "fusectl" | "rpc_pipefs" | "selinuxfs" | "efivarfs"
+            | "nsfs" | "rootfs" => Self::Pseudo,
+            "overlay" | "overlayfs" | "aufs" => Self::Overlay,
+            "squashfs" | "iso9660" | "udf" | "erofs" | "cramfs" => Self::Image,
+            "nfs" | "nfs4" | "cifs" | "smb3" | "smbfs" | "9p" | "afs" | "ceph" | "glusterfs"
+            | "fuse.sshfs" | "fuse.davfs2" | "fuse.rclone" | "fuse.s3fs" => Self::Network,
+            _ if source.starts_with("/dev/loop") => Self::Image,
+            _ if source.starts_with("//") => Self::Network,
+            _ => Self::Disk,
+        }
+    }
+
+    /// why a mount of this kind is not scanned
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::Disk => "disk",
+            Self::Memory => "memory filesystem, opt in with `--include-mounts memory`",
+            Self::Pseudo => "kernel filesystem, opt in with `--include-mounts pseudo`",
+            Self::Overlay => "overlay filesystem, opt in with `--include-mounts overlay`",
+            Self::Image => "mounted image, opt in with `--include-mounts image`",
+            Self::Network => "network filesystem, opt in with `--include-mounts network`",
+            Self::Bind => "bind mount of another disk, opt in with `--include-mounts bind`",
+        }
+    }
+
+    /// why a mount of this kind opted in with `--include-mounts` is scanned
+    pub fn included_reason(self) -> &'static str {
+        match self {
+            Self::Disk => "disk",
+            Self::Memory => "memory filesystem, included",
+            Self::Pseudo => "kernel filesystem, included",
+            Self::Overlay => "overlay filesystem, included",
+            Self::Image => "mounted image, included",
+            Self::Network => "network filesystem, included",
+            Self::Bind => "bind mount of another disk, included",
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Disk => "disk",
+            Self::Memory => "memory",
+            Self::Pseudo => "pseudo",
+            Self::Overlay => "overlay",
+            Self::Image => "image",
+            Self::Network => "network",
+            Self::Bind => "bind",
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::{read_mountinfo, space, Mount};
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        collections::HashSet,
+        io,
+        path::{Path, PathBuf},
+    };
+
+    use super::MountKind;
+
+    /// a line of `/proc/self/mountinfo`
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Mount {
+        /// `major:minor` of the device
+        pub device: String,
+        /// directory of the filesystem that is mounted
+        pub root: String,
+        pub mount_point: PathBuf,
+        pub options: Vec<String>,
+        pub fs_type: String,
+        pub source: String,
+        /// options of the filesystem, as opposed to the ones of the mount
+        pub super_options: Vec<String>,
+        pub kind: MountKind,
+    }
+
+    impl Mount {
+        pub fn is_read_only(&self) -> bool {
+            self.options.iter().any(|x| x == "ro")
+        }
+
+        /// the device, and the subvolume for btrfs whose subvolumes share the device
+        fn filesystem(&self) -> (&str, Option<&str>) {
+            let subvolume = (self.fs_type == "btrfs")
+                .then(|| {
+                    self.super_options
+                        .iter()
+                        .find_map(|x| x.strip_prefix("subvol="))
+                })
+                .flatten();
+            (&self.device, subvolume)
+        }
+    }
+
+    /// `\040` style octal escapes of the kernel
+    fn unescape(field: &str) -> String {
+        let mut out = Vec::with_capacity(field.len());
+        let bytes = field.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            let octal = bytes.get(i + 1..i + 4).and_then(|x| {
+                let x = std::str::from_utf8(x).ok()?;
+                u8::from_str_radix(x, 8).ok()
+            });
+            match (bytes[i], octal) {
+                (b'\\', Some(byte)) => {
+                    out.push(byte);
+                    i += 4;
+                }
+                (byte, _) => {
+                    out.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    /// `id parent major:minor root mount_point options [optional...] - fs_type source super_options`
+    fn parse_line(line: &str) -> Option<Mount> {
+        let (left, right) = line.split_once(" - ")?;
+        let mut left = left.split_whitespace();
+        let (_id, _parent) = (left.next()?, left.next()?);
+        let device = left.next()?.to_owned();
+        let root = unescape(left.next()?);
+        let mount_point = PathBuf::from(unescape(left.next()?));
+        let options = left.next()?.split(',').map(ToOwned::to_owned).collect();
+        let mut right = right.split_whitespace();
+        let fs_type = right.next()?.to_owned();
+        let source = unescape(right.next().unwrap_or_default());
+        let super_options = right
+            .next()
+            .map_or_else(Vec::new, |x| x.split(',').map(ToOwned::to_owned).collect());
+        let kind = MountKind::classify(&fs_type, &source);
+        Some(Mount {
+            device,
+            root,
+            mount_point,
+            options,
+            fs_type,
+            source,
+            super_options,
+            kind,
+        })
+    }
+
+    /// a disk mount of a directory that another visible mount of the same filesystem
+    /// already shows is a bind mount, of two mounts of the same directory the first is kept
+    fn mark_binds(mounts: &mut [Mount]) {
+        let binds: Vec<_> = mounts
+            .iter()
+            .enumerate()
+            .map(|(i, mount)| {
+                mount.kind == MountKind::Disk
+                    && mounts.iter().enumerate().any(|(j, other)| {
+                        j != i
+                            && other.kind == MountKind::Disk
+                            && other.filesystem() == mount.filesystem()
+                            && Path::new(&mount.root).starts_with(&other.root)
+                            && (other.root != mount.root || j < i)
+                    })
+            })
+            .collect();
+        for (mount, bind) in mounts.iter_mut().zip(binds) {
+            if bind {
+                mount.kind = MountKind::Bind;
+            }
+        }
+    }
+
+    /// every visible mount of this process, with the bind mounts of the disks told apart
+    pub fn read_mountinfo() -> io::Result<Vec<Mount>> {
+        let text = std::fs::read_to_string("/proc/self/mountinfo")?;
+        Ok(visible_mounts(&text))
+    }
+
+    /// the mounts of the `mountinfo` table that are not mounted over
+    fn visible_mounts(mountinfo: &str) -> Vec<Mount> {
+        let mut mounts: Vec<_> = mountinfo.lines().filter_map(parse_line).collect();
+        // a mount point mounted over is hidden, only the last mount on it is visible
+        let mut points = HashSet::new();
+        mounts.reverse();
+        mounts.retain(|x| points.insert(x.mount_point.clone()));
+        mounts.reverse();
+        mark_binds(&mut mounts);
+        mounts
+    }
+
+    /// (total, available) bytes of the filesystem mounted at `path`
+    pub fn space(path: &std::path::Path) -> Option<(u64, u64)> {
+        use std::os::unix::ffi::OsStrExt;
+        let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+            return None;
+        }
+        let frsize = stat.f_frsize as u64;
+        Some((stat.f_blocks as u64 * frsize, stat.f_bavail as u64 * frsize))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn unescape_octal() {
+            assert_eq!(unescape(r"/mnt/my\040disk"), "/mnt/my disk");
+            assert_eq!(unescape(r"a\011b\134c"), "a\tb\\c");
+            assert_eq!(unescape(r"trailing\04"), r"trailing\04");
+            assert_eq!(unescape(r"not\999octal"), r"not\999octal");
+        }
+
+        #[test]
+        fn parse_mountinfo_lines() {
+            let btrfs =
+                "36 1 0:32 /@home /home rw,relatime shared:2 master:1 - btrfs /dev/nvme0n1p2 \
+                         rw,ssd,space_cache=v2,subvolid=257,subvol=/@home";
+            let mount = parse_line(btrfs).unwrap();
+            assert_eq!(mount.device, "0:32");
+            assert_eq!(mount.root, "/@home");
+            assert_eq!(mount.mount_point, PathBuf::from("/home"));
+            assert_eq!(mount.fs_type, "btrfs");
+            assert_eq!(mount.source, "/dev/nvme0n1p2");
+            assert_eq!(mount.kind, MountKind::Disk);
+            assert_eq!(mount.filesystem(), ("0:32", Some("/@home")));
+            assert!(!mount.is_read_only());
+
+            let spaced = r"40 25 8:1 /my\040files /mnt/my\040disk ro,nosuid - ext4 /dev/sda1 rw";
+            let mount = parse_line(spaced).unwrap();
+            assert_eq!(mount.root, "/my files");
+            assert_eq!(mount.mount_point, PathBuf::from("/mnt/my disk"));
+            assert_eq!(mount.filesystem(), ("8:1", None));
+            assert!(mount.is_read_only());
+
+            let tmpfs = parse_line("26 25 0:24 / /dev/shm rw - tmpfs tmpfs rw,size=64k").unwrap();
+            assert_eq!(tmpfs.kind, MountKind::Memory);
+            assert_eq!(parse_line("26 25 0:24 / /dev/shm rw"), None);
+        }
+
+        #[test]
+        fn binds_and_subvolumes() {
+            let table = "\
+1 0 0:32 /@ / rw - btrfs /dev/nvme0n1p2 rw,subvolid=256,subvol=/@
+2 1 0:32 /@home /home rw - btrfs /dev/nvme0n1p2 rw,subvolid=257,subvol=/@home
+3 1 0:32 /@home/me/pics /srv/pics rw - btrfs /dev/nvme0n1p2 rw,subvolid=257,subvol=/@home
+4 1 8:1 /containers/root /root rw - ext4 /dev/sda1 rw
+5 1 8:1 /containers/home /data rw - ext4 /dev/sda1 rw
+6 1 8:1 /containers/home/cache /var/cache rw - ext4 /dev/sda1 rw
+7 1 8:1 /containers/root /mnt/again rw - ext4 /dev/sda1 rw
+8 1 0:50 / /mnt/usb rw - tmpfs tmpfs rw
+9 1 8:17 / /mnt/usb rw - vfat /dev/sdb1 rw
+";
+            let mounts = visible_mounts(table);
+            let kind = |point: &str| {
+                mounts
+                    .iter()
+                    .find(|x| x.mount_point == Path::new(point))
+                    .map(|x| x.kind)
+            };
+            assert_eq!(kind("/"), Some(MountKind::Disk));
+            assert_eq!(kind("/home"), Some(MountKind::Disk));
+            assert_eq!(kind("/srv/pics"), Some(MountKind::Bind));
+            assert_eq!(kind("/root"), Some(MountKind::Disk));
+            assert_eq!(kind("/data"), Some(MountKind::Disk));
+            assert_eq!(kind("/var/cache"), Some(MountKind::Bind));
+            assert_eq!(kind("/mnt/again"), Some(MountKind::Bind));
+            // the tmpfs mounted over is hidden
+            assert_eq!(kind("/mnt/usb"), Some(MountKind::Disk));
+            assert_eq!(mounts.len(), 8);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_by_type_and_source() {
+        assert_eq!(MountKind::classify("tmpfs", "tmpfs"), MountKind::Memory);
+        assert_eq!(MountKind::classify("TMPFS", "tmpfs"), MountKind::Memory);
+        assert_eq!(MountKind::classify("proc", "proc"), MountKind::Pseudo);
+        assert_eq!(MountKind::classify("cgroup2", "cgroup2"), MountKind::Pseudo);
+        assert_eq!(
+            MountKind::classify("overlay", "overlay"),
+            MountKind::Overlay
+        );
+        assert_eq!(
+            MountKind::classify("squashfs", "/dev/loop3"),
+            MountKind::Image
+        );
+        assert_eq!(MountKind::classify("ext4", "/dev/loop0"), MountKind::Image);
+        assert_eq!(
+            MountKind::classify("nfs4", "server:/export"),
+            MountKind::Network
+        );
+        assert_eq!(
+            MountKind::classify("fuse.sshfs", "me@host:"),
+            MountKind::Network
+        );
+        assert_eq!(
+            MountKind::classify("smb", "//server/share"),
+            MountKind::Network
+        );
+        assert_eq!(MountKind::classify("ext4", "/dev/sda1"), MountKind::Disk);
+        assert_eq!(
+            MountKind::classify("btrfs", "/dev/nvme0n1p2"),
+            MountKind::Disk
+        );
+    }
+}
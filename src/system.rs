@@ -1,7 +1,8 @@
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use sysinfo::{DiskExt, SystemExt};
 
-use crate::err_log;
+use crate::{err_log, mounts::MountKind};
 
 #[cfg(windows)]
 const ROOT_DIR: &str = "C:";
@@ -14,12 +15,17 @@ fn is_generic_partition(part: &str) -> bool {
 #[cfg(unix)]
 const ROOT_DIR: &str = "/";
 
+/// a partition is not generic when a directory of its mount point is named boot or efi,
+/// `/boot/efi` is but `/mnt/reboot-logs` is not
 #[cfg(unix)]
 fn is_generic_partition(part: &str) -> bool {
-    !(part.contains("efi") || part.contains("boot"))
+    !Path::new(part).components().any(|x| {
+        let x = x.as_os_str().to_string_lossy().to_lowercase();
+        x == "boot" || x == "efi"
+    })
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum DriveType {
     Root,
     Generic,
@@ -27,48 +33,105 @@ pub enum DriveType {
     Boot,
 }
 impl DriveType {
-    fn from_sysinfo_disk(disk: &sysinfo::Disk) -> Self {
-        let p_str = disk.mount_point().to_str();
-        if disk.is_removable() {
-            DriveType::Removable
+    /// the type of `disk`, and why it was given
+    fn from_sysinfo_disk(disk: &sysinfo::Disk) -> (Self, &'static str) {
+        Self::from_mount_point(disk.mount_point(), disk.is_removable())
+    }
+
+    /// the type of the drive mounted at `mount_point`
+    fn from_mount_point(mount_point: &Path, removable: bool) -> (Self, &'static str) {
+        let p_str = mount_point.to_str();
+        if removable {
+            (DriveType::Removable, "removable disk")
         } else if p_str == Some(ROOT_DIR) {
             #[cfg(windows)]
             {
                 path = dirs::home_dir().unwrap_or(path);
-                DriveType::Generic
+                (DriveType::Generic, "system drive")
             }
             #[cfg(unix)]
-            DriveType::Root
+            (DriveType::Root, "mounted at the root")
         } else if p_str.is_some_and(is_generic_partition) {
-            DriveType::Generic
+            (DriveType::Generic, "data partition")
         } else {
-            DriveType::Boot
+            (DriveType::Boot, "mounted in a boot or efi directory")
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct DiskPartition {
-    pub tp: DriveType,
+    /// `None` for the mounts excluded from scanning, which are not drives
+    pub tp: Option<DriveType>,
     pub name: String,
     pub path: PathBuf,
+    pub file_system: String,
+    pub total: u64,
+    pub free: u64,
+    pub kind: MountKind,
+    /// mounted read-only, so never used as destination
+    pub read_only: bool,
+    /// why the drive got its type, or why it is excluded
+    pub reason: &'static str,
 }
 
 impl DiskPartition {
     fn from_sysinfo(part: &sysinfo::Disk) -> Self {
-        let tp = DriveType::from_sysinfo_disk(part);
+        let (tp, reason) = DriveType::from_sysinfo_disk(part);
         let name = part.name().to_str().unwrap_or("").to_owned();
         let path = part.mount_point().to_path_buf();
-        if log::max_level() >= log::LevelFilter::Info {
-            let total = part.total_space() as f32 * 1e-9;
-            let free = part.available_space() as f32 * 1e-9;
-            let used = total - free;
-            eprintln!(
-                "{name:<10} {total:<10} {used:<10} {free:<10} '{mount}'",
-                mount = path.display(),
-            );
+        Self {
+            tp: Some(tp),
+            name,
+            path,
+            file_system: String::from_utf8_lossy(part.file_system()).into_owned(),
+            total: part.total_space(),
+            free: part.available_space(),
+            kind: MountKind::Disk,
+            read_only: false,
+            reason,
         }
-        Self { tp, name, path }
+    }
+
+    /// an `included` mount is a drive, typed by its mount point like the disks
+    #[cfg(target_os = "linux")]
+    fn from_mount(mount: &crate::mounts::Mount, removable: bool, included: bool) -> Self {
+        let (tp, reason) = DriveType::from_mount_point(&mount.mount_point, removable);
+        let (total, free) = crate::mounts::space(&mount.mount_point).unwrap_or_default();
+        Self {
+            tp: included.then_some(tp),
+            name: mount.source.clone(),
+            path: mount.mount_point.clone(),
+            file_system: mount.fs_type.clone(),
+            total,
+            free,
+            kind: mount.kind,
+            read_only: mount.is_read_only(),
+            reason: match mount.kind {
+                MountKind::Disk => reason,
+                kind if included => kind.included_reason(),
+                kind => kind.reason(),
+            },
+        }
+    }
+
+    fn log_row(&self) {
+        let total = self.total as f32 * 1e-9;
+        let free = self.free as f32 * 1e-9;
+        let used = total - free;
+        eprintln!(
+            "{name:<10} {total:<10} {used:<10} {free:<10} '{mount}'",
+            name = self.name,
+            mount = self.path.display(),
+        );
+    }
+
+    /// find the partition that `path` lives on, (the one with longest matching mount point)
+    pub fn find<'d>(drives: &'d [DiskPartition], path: &Path) -> Option<&'d DiskPartition> {
+        drives
+            .iter()
+            .filter(|drive| path.starts_with(&drive.path))
+            .max_by_key(|drive| drive.path.as_os_str().len())
     }
 }
 
@@ -80,11 +143,15 @@ pub struct SystemDiskInfo {
     pub host_name: Option<String>,
     pub default_filename: String,
     pub drives: Vec<DiskPartition>,
+    /// mounts that are not disks and were not opted in with `--include-mounts`
+    pub excluded: Vec<DiskPartition>,
 }
 
 impl SystemDiskInfo {
-    pub fn new() -> Self {
+    /// mounts of the kinds in `include` are kept as drives along the disks
+    pub fn new(include: &[MountKind]) -> Self {
         let sys = sysinfo::System::new_all();
+        let (drives, excluded) = Self::drives(&sys, include);
         if log::max_level() >= log::LevelFilter::Info {
             eprintln!("===================================================");
             eprintln!(
@@ -94,12 +161,8 @@ impl SystemDiskInfo {
                 used = "Used, GB",
                 free = "Free, GB",
             );
+            drives.iter().for_each(DiskPartition::log_row);
         }
-        let drives = sys
-            .disks()
-            .iter()
-            .map(DiskPartition::from_sysinfo)
-            .collect();
         eprintln!("===================================================");
 
         let name = sys.name();
@@ -121,7 +184,39 @@ impl SystemDiskInfo {
             host_name,
             default_filename,
             drives,
+            excluded,
+        }
+    }
+
+    /// on linux every mount of `/proc/self/mountinfo` is classified by its filesystem type,
+    /// elsewhere (or when it can not be read) the disks are the ones of sysinfo
+    fn drives(
+        sys: &sysinfo::System,
+        include: &[MountKind],
+    ) -> (Vec<DiskPartition>, Vec<DiskPartition>) {
+        #[cfg(target_os = "linux")]
+        match crate::mounts::read_mountinfo() {
+            Ok(mounts) => {
+                let removable = |mount: &crate::mounts::Mount| {
+                    sys.disks()
+                        .iter()
+                        .any(|x| x.mount_point() == mount.mount_point && x.is_removable())
+                };
+                let included = |kind: MountKind| kind == MountKind::Disk || include.contains(&kind);
+                return mounts
+                    .iter()
+                    .map(|x| DiskPartition::from_mount(x, removable(x), included(x.kind)))
+                    .partition(|x| included(x.kind));
+            }
+            Err(err) => log::warn!("Failed to read /proc/self/mountinfo - {err}"),
         }
+        let _ = include;
+        let drives = sys
+            .disks()
+            .iter()
+            .map(DiskPartition::from_sysinfo)
+            .collect();
+        (drives, Vec::new())
     }
 
     pub fn dest(&mut self, out: Option<PathBuf>) -> PathBuf {
@@ -146,12 +241,29 @@ impl SystemDiskInfo {
         dest
     }
 
+    /// same as [`SystemDiskInfo::dest`], but for single output file with extension `ext`
+    pub fn dest_file(&mut self, out: Option<PathBuf>, ext: &str) -> PathBuf {
+        match out {
+            Some(out) => out,
+            None => self
+                .removable_drive()
+                .map_or_else(
+                    || {
+                        log::error!("No Removeable Drive");
+                        PathBuf::from(&self.default_filename)
+                    },
+                    |x| x.path.join(&self.default_filename),
+                )
+                .with_extension(ext),
+        }
+    }
+
     #[inline]
     #[allow(unused)]
     pub fn root_drive(&mut self) -> Option<DiskPartition> {
         self.drives
             .iter()
-            .find(|item| matches!(item.tp, DriveType::Root))
+            .find(|item| matches!(item.tp, Some(DriveType::Root)))
             .cloned()
     }
 
@@ -162,7 +274,7 @@ impl SystemDiskInfo {
             .drives
             .iter()
             .filter_map(|item| {
-                if matches!(item.tp, DriveType::Generic) {
+                if matches!(item.tp, Some(DriveType::Generic)) {
                     Some(item.clone())
                 } else {
                     None
@@ -179,11 +291,88 @@ impl SystemDiskInfo {
     pub fn removable_drive(&mut self) -> Option<DiskPartition> {
         self.drives
             .iter()
-            .find(|item| matches!(item.tp, DriveType::Removable))
+            .find(|item| matches!(item.tp, Some(DriveType::Removable)) && !item.read_only)
             .cloned()
     }
 }
 
+/// a drive as listed by `colek drives`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DriveListing {
+    pub name: String,
+    pub mount_point: PathBuf,
+    pub file_system: String,
+    pub kind: MountKind,
+    /// `None` for the excluded mounts
+    #[serde(rename = "type")]
+    pub tp: Option<DriveType>,
+    pub read_only: bool,
+    pub total: u64,
+    pub free: u64,
+    /// files on it are collected
+    pub scanned: bool,
+    /// collected files go to it when no output is given
+    pub destination: bool,
+    pub reason: &'static str,
+}
+
+impl SystemDiskInfo {
+    /// every drive, with what the collecting commands would do with it,
+    /// with `all` the excluded mounts are listed too
+    pub fn listing(&mut self, all: bool) -> Vec<DriveListing> {
+        let destination = self.removable_drive().map(|x| x.path);
+        let excluded = self.excluded.iter().filter(|_| all);
+        self.drives
+            .iter()
+            .map(|x| (x, true))
+            .chain(excluded.map(|x| (x, false)))
+            .map(|(x, included)| DriveListing {
+                name: x.name.clone(),
+                mount_point: x.path.clone(),
+                file_system: x.file_system.clone(),
+                kind: x.kind,
+                tp: x.tp,
+                read_only: x.read_only,
+                total: x.total,
+                free: x.free,
+                scanned: included && x.tp == Some(DriveType::Generic),
+                destination: destination.as_ref() == Some(&x.path),
+                reason: x.reason,
+            })
+            .collect()
+    }
+
+    pub fn print_drives(&mut self, json: bool, all: bool) -> crate::Result<()> {
+        let listing = self.listing(all);
+        if json {
+            serde_json::to_writer_pretty(std::io::stdout().lock(), &listing)?;
+            println!();
+            return Ok(());
+        }
+        println!(
+            "{:<16} {:<10} {:<8} {:<9} {:>10} {:>10} {:<8} {:<11} Mount",
+            "Device", "Fs", "Kind", "Type", "Total, GB", "Free, GB", "Scanned", "Destination"
+        );
+        let yes = |x: bool| if x { "yes" } else { "no" };
+        for x in &listing {
+            println!(
+                "{:<16} {:<10} {:<8} {:<9} {:>10.2} {:>10.2} {:<8} {:<11} '{}' ({})",
+                x.name,
+                x.file_system,
+                x.kind.as_str(),
+                x.tp.map_or_else(|| "-".to_owned(), |x| format!("{x:?}")),
+                x.total as f64 * 1e-9,
+                x.free as f64 * 1e-9,
+                yes(x.scanned),
+                yes(x.destination),
+                x.mount_point.display(),
+                x.reason,
+            );
+        }
+        Ok(())
+    }
+}
+
 impl std::fmt::Display for SystemDiskInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(ref name) = self.name {
@@ -0,0 +1,151 @@
+//! Sending files to the trash following the freedesktop.org Trash specification,
+//! so they can be restored from any file manager.
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub use freedesktop::put;
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+pub fn put(_path: &std::path::Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "trash is only supported on freedesktop platforms",
+    ))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod freedesktop {
+    use std::{
+        fs::{self, OpenOptions},
+        io::{self, Write},
+        os::unix::{
+            ffi::OsStrExt,
+            fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+        },
+        path::{Path, PathBuf},
+    };
+
+    /// move `path` into the trash of the volume it lives on
+    pub fn put(path: &Path) -> io::Result<()> {
+        let path = std::path::absolute(path)?;
+        let trash = trash_dir(&path)?;
+        let files = trash.join("files");
+        let info = trash.join("info");
+        for dir in [&files, &info] {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)?;
+        }
+
+        let name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+        // the .trashinfo is created exclusively first, it reserve the name in files/
+        for n in 0u32.. {
+            let mut trashed = name.to_owned();
+            if n > 0 {
+                trashed.push(format!(".{n}"));
+            }
+            let mut info_name = trashed.clone();
+            info_name.push(".trashinfo");
+            let info_path = info.join(info_name);
+            let mut info_file = match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&info_path)
+            {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            };
+            let trashed = files.join(trashed);
+            if trashed.symlink_metadata().is_ok() {
+                drop(info_file);
+                fs::remove_file(&info_path).ok();
+                continue;
+            }
+            write!(
+                info_file,
+                "[Trash Info]\nPath={}\nDeletionDate={}\n",
+                encode_path(&path),
+                deletion_date()
+            )?;
+            return fs::rename(&path, &trashed).inspect_err(|_| {
+                fs::remove_file(&info_path).ok();
+            });
+        }
+        unreachable!("the trash can not hold more than u32::MAX files with the same name")
+    }
+
+    /// home trash when the file is on the same device, otherwise the trash at the top of its volume
+    fn trash_dir(path: &Path) -> io::Result<PathBuf> {
+        let dev = path.symlink_metadata()?.dev();
+        let home_trash = dirs::data_dir().map(|x| x.join("Trash"));
+        if let Some(home_trash) = home_trash {
+            let home_dev = home_trash
+                .ancestors()
+                .find_map(|x| x.metadata().ok())
+                .map(|x| x.dev());
+            if home_dev == Some(dev) {
+                return Ok(home_trash);
+            }
+        }
+
+        let top = top_dir(path, dev);
+        // SAFETY: getuid is always successful
+        let uid = unsafe { libc::getuid() };
+        let shared = top.join(".Trash");
+        let sticky = shared.symlink_metadata().is_ok_and(|x| {
+            x.is_dir() && !x.file_type().is_symlink() && x.permissions().mode() & 0o1000 != 0
+        });
+        if sticky {
+            return Ok(shared.join(uid.to_string()));
+        }
+        Ok(top.join(format!(".Trash-{uid}")))
+    }
+
+    /// the mount point containing `path`, the last ancestor still on device `dev`
+    fn top_dir(path: &Path, dev: u64) -> PathBuf {
+        let mut top = path.parent().unwrap_or(path);
+        while let Some(parent) = top.parent() {
+            if parent.metadata().map_or(true, |x| x.dev() != dev) {
+                break;
+            }
+            top = parent;
+        }
+        top.to_path_buf()
+    }
+
+    /// percent-encode everything except unreserved characters and `/`
+    fn encode_path(path: &Path) -> String {
+        let mut out = String::new();
+        for &byte in path.as_os_str().as_bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                    out.push(byte as char)
+                }
+                _ => out.push_str(&format!("%{byte:02X}")),
+            }
+        }
+        out
+    }
+
+    /// local time as `YYYY-MM-DDThh:mm:ss`
+    fn deletion_date() -> String {
+        // SAFETY: localtime_r only write into the given `tm`
+        unsafe {
+            let now = libc::time(std::ptr::null_mut());
+            let mut tm: libc::tm = std::mem::zeroed();
+            libc::localtime_r(&now, &mut tm);
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                tm.tm_year + 1900,
+                tm.tm_mon + 1,
+                tm.tm_mday,
+                tm.tm_hour,
+                tm.tm_min,
+                tm.tm_sec
+            )
+        }
+    }
+}